@@ -0,0 +1,60 @@
+/// Check if `text` matches the shell-style wildcard `pattern`.
+///
+/// `*` matches any run of characters other than `/`, `?` matches any
+/// single character other than `/`, and `**` matches across
+/// directory separators. A `**/` can also match nothing at all, so
+/// `**/foo` matches both `foo` and `a/b/foo`.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+    matches(&pattern, &text)
+}
+
+fn matches(pattern: &[char], text: &[char]) -> bool {
+    match pattern.first() {
+        None => text.is_empty(),
+        Some('*') if pattern.get(1) == Some(&'*') => {
+            let rest = &pattern[2..];
+            if rest.first() == Some(&'/') && matches(&rest[1..], text) {
+                return true;
+            }
+            (0..=text.len()).any(|i| matches(rest, &text[i..]))
+        }
+        Some('*') => {
+            let rest = &pattern[1..];
+            for i in 0..=text.len() {
+                if matches(rest, &text[i..]) {
+                    return true;
+                }
+                if i < text.len() && text[i] == '/' {
+                    break;
+                }
+            }
+            false
+        }
+        Some('?') => {
+            !text.is_empty()
+                && text[0] != '/'
+                && matches(&pattern[1..], &text[1..])
+        }
+        Some(c) => {
+            text.first() == Some(c) && matches(&pattern[1..], &text[1..])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*.log", "app.log"));
+        assert!(!glob_match("*.log", "logs/app.log"));
+        assert!(glob_match("logs/?.txt", "logs/a.txt"));
+        assert!(glob_match("**/*.log", "app.log"));
+        assert!(glob_match("**/*.log", "a/b/app.log"));
+        assert!(glob_match("a/**", "a/b/c"));
+        assert!(!glob_match("a/*", "a/b/c"));
+    }
+}
@@ -0,0 +1,127 @@
+use anyhow::{anyhow, Error};
+use fehler::{throw, throws};
+use std::borrow::Cow;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use tar::{Builder, EntryType, Header};
+
+/// Name of the GNU entries that hold a long path for the next entry.
+const LONG_LINK_NAME: &[u8] = b"././@LongLink";
+
+/// Get the bytes stored in an archive for the link target `target`.
+#[cfg(unix)]
+fn link_bytes(target: &Path) -> Option<Cow<'_, [u8]>> {
+    use std::os::unix::ffi::OsStrExt;
+
+    Some(Cow::Borrowed(target.as_os_str().as_bytes()))
+}
+
+#[cfg(not(unix))]
+fn link_bytes(target: &Path) -> Option<Cow<'_, [u8]>> {
+    Some(Cow::Owned(target.to_str()?.replace('\\', "/").into_bytes()))
+}
+
+/// Append a symlink or hard link entry with header `header` to
+/// `builder`, linking `path` to `target`.
+///
+/// The link field in the header only holds 100 bytes, so longer
+/// targets are written to a GNU long link entry just before it, as
+/// GNU tar does.
+#[throws]
+pub fn append_link<W: Write, R: Read>(
+    builder: &mut Builder<W>,
+    header: &mut Header,
+    path: &Path,
+    target: &Path,
+    data: R,
+) {
+    if let Err(err) = header.set_link_name(target) {
+        let bytes = match link_bytes(target) {
+            Some(bytes) => bytes,
+            None => throw!(anyhow!(
+                "can't store link target for {}: {}",
+                path.display(),
+                err
+            )),
+        };
+        let linkname = &mut header.as_old_mut().linkname;
+        if bytes.len() <= linkname.len() || bytes.contains(&0) {
+            // Not a problem with the length, so the long link entry
+            // won't help
+            throw!(anyhow!(
+                "can't store link target for {}: {}",
+                path.display(),
+                err
+            ));
+        }
+        // Readers that don't know about long links get the start
+        let len = linkname.len();
+        linkname.copy_from_slice(&bytes[..len]);
+
+        let mut long_header = Header::new_gnu();
+        // OK to unwrap: this is a GNU header
+        long_header.as_gnu_mut().unwrap().name[..LONG_LINK_NAME.len()]
+            .copy_from_slice(LONG_LINK_NAME);
+        long_header.set_mode(0o644);
+        long_header.set_uid(0);
+        long_header.set_gid(0);
+        long_header.set_mtime(0);
+        // Includes a nul terminator, as GNU tar writes
+        long_header.set_size(bytes.len() as u64 + 1);
+        long_header.set_entry_type(EntryType::GNULongLink);
+        long_header.set_cksum();
+        builder.append(&long_header, bytes.chain(io::repeat(0).take(1)))?;
+    }
+    builder.append_data(header, path, data)?;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tar::Archive;
+
+    #[test]
+    fn test_append_link() {
+        let long_target = "t/".repeat(75);
+        let mut builder = Builder::new(Vec::new());
+        for target in ["short", long_target.as_str()] {
+            let mut header = Header::new_gnu();
+            header.set_entry_type(EntryType::Symlink);
+            header.set_size(0);
+            append_link(
+                &mut builder,
+                &mut header,
+                Path::new("link"),
+                Path::new(target),
+                io::empty(),
+            )
+            .unwrap();
+        }
+        let data = builder.into_inner().unwrap();
+
+        let mut archive = Archive::new(data.as_slice());
+        let targets = archive
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                entry.link_name().unwrap().unwrap().display().to_string()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(targets, vec!["short".to_string(), long_target]);
+
+        let mut header = Header::new_gnu();
+        header.set_entry_type(EntryType::Symlink);
+        let err = append_link(
+            &mut Builder::new(Vec::new()),
+            &mut header,
+            Path::new("link"),
+            Path::new("a\0b"),
+            io::empty(),
+        )
+        .unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("can't store link target for link"));
+    }
+}
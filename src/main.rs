@@ -1,18 +1,51 @@
+mod glob;
+mod link;
+mod pack;
+mod repack;
+mod split;
+mod timeout;
+mod transform;
+
 use anyhow::{anyhow, Error};
 use argh::FromArgs;
-use fehler::throws;
+use fehler::{throw, throws};
+use filetime::FileTime;
+use glob::glob_match;
 use humansize::{file_size_opts as options, FileSize};
+use split::{parse_size, SplitWriter};
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::env;
 use std::ffi::OsStr;
 use std::fs::{self, File};
-use std::io::Read;
-use std::path::{Path, PathBuf};
-use tar::Archive;
-use unicode_width::UnicodeWidthStr;
+use std::hash::Hasher;
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+#[cfg(unix)]
+use std::sync::OnceLock;
+use std::time::Duration;
+use tar::{Archive, Builder, Entry};
+use timeout::TimeoutReader;
+use transform::PathTransform;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
 /// Tarball utility.
 #[derive(Debug, FromArgs)]
 struct Opt {
+    /// exit with an error if there were any warnings, after
+    /// completing the command
+    #[argh(switch)]
+    fail_on_warning: bool,
+
+    /// fail if reading the tarball makes no progress for this many
+    /// seconds
+    #[argh(option)]
+    timeout: Option<u64>,
+
     #[argh(subcommand)]
     command: Command,
 }
@@ -20,242 +53,4558 @@ struct Opt {
 #[derive(Debug, FromArgs)]
 #[argh(subcommand)]
 enum Command {
+    CatAll(CatAllCommand),
+    Check(CheckCommand),
     List(ListCommand),
-    // TODO
-    // Pack,
+    Pack(PackCommand),
+    Repack(RepackCommand),
     Unpack(UnpackCommand),
 }
 
+/// Write the contents of every regular file in a tarball to stdout, in
+/// tarball order.
+#[derive(Debug, Default, FromArgs)]
+#[argh(subcommand, name = "cat-all")]
+struct CatAllCommand {
+    #[argh(positional)]
+    tarball: PathBuf,
+
+    /// line to print between files
+    #[argh(option)]
+    separator: Option<String>,
+}
+
+/// Check that a tarball is valid.
+#[derive(Debug, Default, FromArgs)]
+#[argh(subcommand, name = "check")]
+struct CheckCommand {
+    #[argh(positional)]
+    tarball: PathBuf,
+
+    /// check that the tarball is reproducible: entries are sorted by
+    /// path and have no timestamps, owners, or other metadata that
+    /// varies between builds
+    #[argh(switch)]
+    reproducible: bool,
+
+    /// check that each entry's data is as long as the size in its
+    /// header says, which fails for truncated or forged tarballs
+    #[argh(switch)]
+    size_mismatch: bool,
+}
+
 /// List the contents of a tarball.
-#[derive(Debug, FromArgs)]
+#[derive(Debug, Default, FromArgs)]
 #[argh(subcommand, name = "list")]
 struct ListCommand {
     #[argh(positional)]
     tarball: PathBuf,
+
+    /// report groups of files that have identical contents
+    #[argh(switch)]
+    find_dupes: bool,
+
+    /// print the number and total size of files with each extension
+    #[argh(switch)]
+    by_extension: bool,
+
+    /// only list entries under this directory, with paths shown
+    /// relative to it
+    #[argh(option)]
+    relative_to: Option<PathBuf>,
+
+    /// only print entry paths, one per line, without sizes
+    #[argh(switch)]
+    names_only: bool,
+
+    /// print the number of entries and the tarball size compared to
+    /// the unpacked size, instead of listing entries
+    #[argh(switch)]
+    summary: bool,
+
+    /// show the absolute path each entry would be unpacked to
+    #[argh(switch)]
+    as_extracted: bool,
+
+    /// directory that --as-extracted paths are relative to (default:
+    /// current directory)
+    #[argh(option, short = 'C')]
+    directory: Option<PathBuf>,
+
+    /// only read the first N entries of the tarball
+    #[argh(option)]
+    limit: Option<usize>,
+
+    /// also show how many 512-byte blocks each entry takes up in the
+    /// tarball, including its header and padding
+    #[argh(switch)]
+    blocks: bool,
+
+    /// mark each file as [text] or [binary] based on the start of its
+    /// contents
+    #[argh(switch)]
+    classify_content: bool,
+
+    /// estimate how well the files under each top-level directory
+    /// would compress, instead of listing entries; this reads the
+    /// start of every file
+    #[argh(switch)]
+    compressibility_by_dir: bool,
+
+    /// show the target of each symlink, and what the target is if it's
+    /// in the tarball
+    #[argh(switch)]
+    resolve_internal_links: bool,
+
+    /// print the metadata recorded by `pack --git-describe`, instead of
+    /// listing entries
+    #[argh(switch)]
+    show_meta: bool,
+
+    /// number of decimal places to show in sizes (default: 2)
+    #[argh(option)]
+    precision: Option<usize>,
+
+    /// output format: text (default) or csv, which prints
+    /// path,size,mode,mtime,type rows after a header line
+    #[argh(option, default = "ListFormat::Text")]
+    format: ListFormat,
+
+    /// only list entries whose mode has all of these bits set, in
+    /// octal; for example, 4000 lists setuid entries
+    #[argh(option, from_str_fn(parse_octal_mode))]
+    mode_mask: Option<u32>,
+
+    /// only list entries with exactly this mode, in octal
+    #[argh(option, from_str_fn(parse_octal_mode))]
+    mode_exact: Option<u32>,
+
+    /// only list entries of this type: file, dir, symlink, link (hard
+    /// link), or other
+    #[argh(option, long = "type", from_str_fn(parse_entry_type))]
+    entry_type: Option<&'static str>,
+
+    /// only list entries matching this pattern; patterns without a /
+    /// match the file name, others the whole path
+    #[argh(option, long = "match")]
+    match_pattern: Option<String>,
+
+    /// how --type, --match, --mode-mask, and --mode-exact combine: and
+    /// (default), listing entries that pass every filter, or or,
+    /// listing entries that pass any of them
+    #[argh(option, default = "FilterLogic::And")]
+    filter_logic: FilterLogic,
+
+    /// only list entries that would overwrite something that already
+    /// exists when unpacking into this directory
+    #[argh(option)]
+    conflicts_with: Option<PathBuf>,
+
+    /// sort directories before files at each level of the tree, rather
+    /// than sorting everything by path
+    #[argh(switch)]
+    dirs_first: bool,
+
+    /// order of entries: path (default) or compressibility, which
+    /// lists files from most to least compressible; this reads the
+    /// start of every file, so it's slow on large tarballs
+    #[argh(option, default = "ListSort::Path")]
+    sort: ListSort,
+
+    /// maximum width of each line in columns; long paths are shortened
+    /// to fit alongside the sizes (default: no limit)
+    #[argh(option)]
+    width: Option<usize>,
+}
+
+/// Create a tarball from the contents of a directory.
+///
+/// Paths in the tarball are relative to the source directory. Paths
+/// matching the patterns in a .tarignore file in the source directory
+/// are excluded.
+#[derive(Debug, Default, FromArgs)]
+#[argh(subcommand, name = "pack")]
+struct PackCommand {
+    #[argh(positional)]
+    source: PathBuf,
+
+    /// path of the tarball to create
+    #[argh(option, short = 'o')]
+    output: PathBuf,
+
+    /// don't read exclusion patterns from the .tarignore file
+    #[argh(switch)]
+    no_ignore_file: bool,
+
+    /// print each file as it's added; pass twice, as "-v -v", to also
+    /// print the size of the tarball so far
+    #[argh(switch, short = 'v')]
+    verbose: u8,
+
+    /// put every entry under a single top-level directory with this
+    /// name
+    #[argh(option)]
+    single_root: Option<String>,
+
+    /// record the output of `git describe` for the source directory in
+    /// a .tarr-meta entry
+    #[argh(switch)]
+    git_describe: bool,
+
+    /// store files that are hard links to an already-added file as
+    /// link entries rather than storing their contents again (unix
+    /// only)
+    #[argh(switch)]
+    detect_hardlinks: bool,
+
+    /// store files with the same contents as an already-added file as
+    /// hard links to it
+    #[argh(switch)]
+    dedup_content: bool,
+
+    /// split the tarball into volumes of at most this size, such as
+    /// 100MiB, named after the output with .001, .002, etc. appended
+    #[argh(option, from_str_fn(parse_size))]
+    split: Option<u64>,
+}
+
+/// Copy a tarball to a new one, changing entry paths the same way
+/// unpack does.
+///
+/// Either path can be - to read from stdin or write to stdout, so that
+/// tarballs can be filtered in a pipeline. Put -- before the paths so
+/// that - isn't taken as an option, as in `tarr repack -- - -`. If the
+/// output is left out or is the input itself, the input is replaced.
+#[derive(Debug, Default, FromArgs)]
+#[argh(subcommand, name = "repack")]
+struct RepackCommand {
+    #[argh(positional)]
+    input: PathBuf,
+
+    #[argh(positional)]
+    output: Option<PathBuf>,
+
+    /// remove N leading components from entry paths, skipping entries
+    /// with no more than N components
+    #[argh(option, default = "0")]
+    strip_components: usize,
+
+    /// skip entries matching this pattern; can be repeated
+    #[argh(option)]
+    exclude: Vec<String>,
+
+    /// normalize the tarball: keep only the last entry with each path,
+    /// sort entries by path, and clear timestamps and owners
+    #[argh(switch)]
+    optimize: bool,
 }
 
 /// Unpack the contents of a tarball.
-#[derive(Debug, FromArgs)]
+///
+/// The tarball can be - to read it from stdin. Put -- before it so that
+/// it isn't taken as an option, as in `tarr unpack -- -`.
+#[derive(Debug, Default, FromArgs)]
 #[argh(subcommand, name = "unpack")]
 struct UnpackCommand {
     #[argh(positional)]
     tarball: PathBuf,
+
+    /// read each unpacked file back and check that it matches the
+    /// tarball
+    #[argh(switch)]
+    verify: bool,
+
+    /// directory to unpack into (default: current directory)
+    #[argh(option, short = 'C')]
+    directory: Option<PathBuf>,
+
+    /// unpack every entry strictly in tarball order, including
+    /// directories, which are otherwise unpacked last so that their
+    /// permissions can't block unpacking their contents
+    #[argh(switch)]
+    preserve_order: bool,
+
+    /// set the modification time of unpacked files to the current time
+    /// instead of the time stored in the tarball
+    #[argh(switch)]
+    mtime_now: bool,
+
+    /// only unpack the first N entries of the tarball
+    #[argh(option)]
+    limit: Option<usize>,
+
+    /// only replace existing files that are older than the ones in the
+    /// tarball
+    #[argh(switch)]
+    only_newer: bool,
+
+    /// command to run for each unpacked file, with {} replaced by the
+    /// file's path; it's split on whitespace and run without a shell
+    #[argh(option)]
+    exec: Option<String>,
+
+    /// stop with an error if an --exec command fails, rather than
+    /// printing a warning and continuing
+    #[argh(switch)]
+    exec_fail_stop: bool,
+
+    /// keep the exact permissions from the tarball rather than
+    /// applying the umask to them
+    #[argh(switch, short = 'p')]
+    same_permissions: bool,
+
+    /// where to print informational messages: stdout or stderr
+    /// (default)
+    #[argh(option, default = "MessageDest::Stderr")]
+    messages: MessageDest,
+
+    /// print where the tarball would be unpacked and how many files it
+    /// has, without unpacking anything
+    #[argh(switch)]
+    describe: bool,
+
+    /// don't print anything if unpacking succeeds; if it fails, the
+    /// error is printed along with any messages held back
+    #[argh(switch)]
+    quiet_summary: bool,
+
+    /// when done, print a JSON object with the destination, the number
+    /// of files and bytes written, the number of entries skipped, and
+    /// any warnings, instead of the usual message
+    #[argh(switch)]
+    summary_json: bool,
+
+    /// remove N leading components from entry paths, skipping entries
+    /// with no more than N components
+    #[argh(option, default = "0")]
+    strip_components: usize,
+
+    /// skip entries matching this pattern; can be repeated
+    #[argh(option)]
+    exclude: Vec<String>,
+
+    /// unpack straight into the destination rather than into a
+    /// temporary directory that's moved into place at the end; each
+    /// file is written as NAME.partial and renamed once it's complete;
+    /// fails if the unpacked files' location already exists, unless
+    /// resuming with --resume-state
+    #[argh(switch)]
+    direct: bool,
+
+    /// warn about entries that would be world-writable, setuid, or
+    /// setgid
+    #[argh(switch)]
+    warn_permissions: bool,
+
+    /// like --warn-permissions, but stop with an error instead of
+    /// warning
+    #[argh(switch)]
+    strict: bool,
+
+    /// only unpack entries of these types, separated by commas, such
+    /// as file,dir; others are skipped with a warning. Types are file,
+    /// dir, symlink, link (hard link), and other
+    #[argh(option, from_str_fn(parse_entry_types))]
+    allow_types: Option<Vec<&'static str>>,
+
+    /// name to use for the tarball when it's read from stdin, which
+    /// sets the name of the directory it's unpacked into if one is
+    /// needed (default: archive)
+    #[argh(option, from_str_fn(parse_stdin_name))]
+    stdin_name: Option<String>,
+
+    /// always unpack into a new directory named after the tarball, even
+    /// if it only has one top-level item
+    #[argh(switch)]
+    always_subdir: bool,
+
+    /// permissions, in octal, of the directory created to hold the
+    /// tarball's contents, if one is needed, and of any directories
+    /// created by --create-dest (unix only)
+    #[argh(option, from_str_fn(parse_octal_mode))]
+    dir_mode: Option<u32>,
+
+    /// refuse to unpack if any directory in the destination path is a
+    /// symlink, so nothing is written somewhere unexpected
+    #[argh(switch)]
+    no_follow_dest_symlinks: bool,
+
+    /// the tarball is the first volume, ending in .001, of one made
+    /// with `pack --split`; the other volumes are read after it
+    #[argh(switch)]
+    multi_volume: bool,
+
+    /// create the destination directory, and any missing parents, if it
+    /// doesn't exist
+    #[argh(switch)]
+    create_dest: bool,
+
+    /// skip entries whose unpacked path would be longer than Windows
+    /// allows without the \\?\ prefix, rather than unpacking them
+    /// with the prefix
+    #[argh(switch)]
+    skip_long_paths: bool,
+
+    /// with --direct, record finished entries in this file so that an
+    /// interrupted unpack can be resumed by running it again; the file
+    /// is removed once everything is unpacked
+    #[argh(option)]
+    resume_state: Option<PathBuf>,
+}
+
+/// Output format of the list command.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+enum ListFormat {
+    #[default]
+    Text,
+    Csv,
+}
+
+impl FromStr for ListFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ListFormat, String> {
+        match s {
+            "text" => Ok(ListFormat::Text),
+            "csv" => Ok(ListFormat::Csv),
+            _ => Err(format!("invalid list format: {}", s)),
+        }
+    }
+}
+
+/// Order of entries printed by the list command.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+enum ListSort {
+    #[default]
+    Path,
+    Compressibility,
+}
+
+impl FromStr for ListSort {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ListSort, String> {
+        match s {
+            "path" => Ok(ListSort::Path),
+            "compressibility" => Ok(ListSort::Compressibility),
+            _ => Err(format!("invalid sort order: {}", s)),
+        }
+    }
+}
+
+/// How the list command's filters combine.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+enum FilterLogic {
+    #[default]
+    And,
+    Or,
+}
+
+impl FromStr for FilterLogic {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<FilterLogic, String> {
+        match s {
+            "and" => Ok(FilterLogic::And),
+            "or" => Ok(FilterLogic::Or),
+            _ => Err(format!("invalid filter logic: {}", s)),
+        }
+    }
+}
+
+/// Parse an entry type name, as returned by `entry_type_name`.
+fn parse_entry_type(s: &str) -> Result<&'static str, String> {
+    ["file", "dir", "symlink", "link", "other"]
+        .into_iter()
+        .find(|name| *name == s)
+        .ok_or_else(|| format!("invalid entry type: {}", s))
+}
+
+/// Parse `--stdin-name`, which must be a single file name that
+/// `file_stem` can name a directory after.
+fn parse_stdin_name(s: &str) -> Result<String, String> {
+    let path = Path::new(s);
+    let is_name = matches!(
+        path.components().collect::<Vec<_>>().as_slice(),
+        [Component::Normal(_)]
+    );
+    if is_name && file_stem(path).is_some() {
+        Ok(s.to_string())
+    } else {
+        Err(format!("invalid stdin name: {}", s))
+    }
+}
+
+/// Parse a comma-separated list of entry type names.
+fn parse_entry_types(s: &str) -> Result<Vec<&'static str>, String> {
+    s.split(',').map(parse_entry_type).collect()
+}
+
+/// Check if the entry path `path` matches `pattern`. Patterns without
+/// a `/` are matched against the file name only.
+fn path_matches(pattern: &str, path: &str) -> bool {
+    let path = path.trim_end_matches('/');
+    if pattern.contains('/') {
+        glob_match(pattern, path)
+    } else {
+        let name = path.rsplit('/').next().unwrap_or(path);
+        glob_match(pattern, name)
+    }
+}
+
+/// Parse a file mode written in octal, such as "755".
+fn parse_octal_mode(s: &str) -> Result<u32, String> {
+    match u32::from_str_radix(s, 8) {
+        Ok(mode) if mode <= 0o7777 => Ok(mode),
+        _ => Err(format!("invalid mode: {}", s)),
+    }
+}
+
+impl UnpackCommand {
+    /// Check if entries of type `entry_type` can be unpacked, given
+    /// `--allow-types`.
+    fn allows_type(&self, entry_type: tar::EntryType) -> bool {
+        match &self.allow_types {
+            Some(types) => types.contains(&entry_type_name(entry_type)),
+            None => true,
+        }
+    }
+
+    /// Get the path of the tarball, for naming the directory it's
+    /// unpacked into. When reading from stdin this is `--stdin-name`.
+    fn tarball_name(&self) -> &Path {
+        if self.tarball != Path::new(STDIO_PATH) {
+            return &self.tarball;
+        }
+        match &self.stdin_name {
+            Some(name) => Path::new(name),
+            None => Path::new(DEFAULT_STDIN_NAME),
+        }
+    }
+
+    /// Get the transform applied to entry paths by `--strip-components`
+    /// and `--exclude`.
+    fn transform(&self) -> PathTransform {
+        PathTransform {
+            strip_components: self.strip_components,
+            exclude: self.exclude.clone(),
+        }
+    }
+}
+
+/// Name used for a tarball read from stdin if `--stdin-name` isn't
+/// set.
+const DEFAULT_STDIN_NAME: &str = "archive";
+
+/// Where informational messages are printed.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+enum MessageDest {
+    Stdout,
+    /// Default, so that stdout stays clean for piping
+    #[default]
+    Stderr,
+}
+
+impl MessageDest {
+    fn print(self, s: &str) {
+        match self {
+            MessageDest::Stdout => print_str(s),
+            MessageDest::Stderr => eprint_str(s),
+        }
+    }
+}
+
+impl FromStr for MessageDest {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<MessageDest, String> {
+        match s {
+            "stdout" => Ok(MessageDest::Stdout),
+            "stderr" => Ok(MessageDest::Stderr),
+            _ => Err(format!("invalid message destination: {}", s)),
+        }
+    }
+}
+
+/// Non-fatal problems found while running a command.
+///
+/// These are printed once the command has finished, and cause a
+/// nonzero exit if `--fail-on-warning` is set.
+#[derive(Debug, Default)]
+struct Warnings {
+    messages: Vec<String>,
+}
+
+impl Warnings {
+    fn warn(&mut self, message: String) {
+        self.messages.push(message);
+    }
 }
 
 fn print_str(s: &str) {
     println!("{}", s);
 }
 
+fn eprint_str(s: &str) {
+    eprintln!("{}", s);
+}
+
+/// Number of decimal places shown in formatted sizes by default.
+const DEFAULT_SIZE_PRECISION: usize = 2;
+
+/// Format a size in bytes for display.
 #[throws]
-fn list_tarball_impl<R: Read, P: FnMut(&str)>(
+fn format_size(size: u64) -> String {
+    format_size_with_precision(size, DEFAULT_SIZE_PRECISION)?
+}
+
+/// Format a size in bytes for display, with `decimal_places` digits
+/// after the decimal point. Whole numbers are shown without any.
+#[throws]
+fn format_size_with_precision(size: u64, decimal_places: usize) -> String {
+    let opts = options::FileSizeOpts {
+        decimal_places,
+        ..options::BINARY
+    };
+    size.file_size(opts).map_err(|err| anyhow!(err))?
+}
+
+/// Size in bytes of the blocks that a tarball is made of.
+const BLOCK_SIZE: u64 = 512;
+
+/// Get the number of blocks taken up by an entry with `size` bytes of
+/// data: one for the header, plus the data padded to a whole number
+/// of blocks. Extra headers for long paths aren't counted.
+fn entry_blocks(size: u64) -> u64 {
+    1 + size.div_ceil(BLOCK_SIZE)
+}
+
+/// Number of bytes at the start of each file that --classify-content
+/// looks at.
+const CLASSIFY_SAMPLE_SIZE: u64 = 8192;
+
+/// Roughly classify a file as "text" or "binary" from `sample`, the
+/// start of its contents: it's text if it's valid UTF-8 without any
+/// NUL bytes.
+fn classify_content(sample: &[u8]) -> &'static str {
+    if sample.contains(&0) {
+        return "binary";
+    }
+    match std::str::from_utf8(sample) {
+        Ok(_) => "text",
+        // The sample may end partway through a character
+        Err(err) if err.error_len().is_none() => "text",
+        Err(_) => "binary",
+    }
+}
+
+/// Shorten `path` to fit in `width` columns by replacing the start of
+/// it with "…", so that the file name stays visible.
+fn truncate_path(path: &str, width: usize) -> Cow<'_, str> {
+    if path.width() <= width {
+        return Cow::Borrowed(path);
+    }
+    if width == 0 {
+        return Cow::Borrowed("");
+    }
+
+    // Keep as much of the end as fits, leaving room for the ellipsis
+    let mut kept_width = 0;
+    let mut start = path.len();
+    for (index, c) in path.char_indices().rev() {
+        kept_width += c.width().unwrap_or(0);
+        if kept_width + 1 > width {
+            break;
+        }
+        start = index;
+    }
+    Cow::Owned(format!("…{}", &path[start..]))
+}
+
+/// Writer that passes data through to an inner writer while also
+/// feeding it into a hasher.
+struct HashWriter<W: Write> {
+    inner: W,
+    hasher: DefaultHasher,
+}
+
+impl<W: Write> HashWriter<W> {
+    fn new(inner: W) -> HashWriter<W> {
+        HashWriter {
+            inner,
+            hasher: DefaultHasher::new(),
+        }
+    }
+}
+
+impl<W: Write> Write for HashWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        self.hasher.write(&buf[..len]);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Hash everything that can be read from `reader`.
+///
+/// This uses the standard library's default hasher, so the values are
+/// only meant for comparing data within a single run, not for storing.
+#[throws(io::Error)]
+fn hash_reader<R: Read>(mut reader: R) -> u64 {
+    let mut writer = HashWriter::new(io::sink());
+    io::copy(&mut reader, &mut writer)?;
+    writer.hasher.finish()
+}
+
+/// Print groups of regular files whose contents are identical.
+///
+/// Each group is printed as its shared content hash followed by the
+/// indented paths of its members. Files are grouped by size as well as
+/// hash, so that a hash collision alone can't group different files.
+#[throws]
+fn list_duplicates_impl<R: Read, P: FnMut(&str)>(
     archive: &mut Archive<R>,
     mut print: P,
 ) {
-    struct Entry {
-        path: String,
-        size: String,
+    // Maps size and hash to the paths of the files with them
+    let mut groups: BTreeMap<(u64, u64), Vec<String>> = BTreeMap::new();
+    for file in archive.entries()? {
+        let file = file?;
+        if !file.header().entry_type().is_file() {
+            continue;
+        }
+        let path = file.path()?.display().to_string();
+        let size = file.header().size()?;
+        let hash = hash_reader(file)?;
+        groups.entry((size, hash)).or_default().push(path);
     }
 
-    let mut max_path_columns = 0;
-    let mut entries = archive
-        .entries()?
-        .map(|file| -> Result<Entry, Error> {
-            let file = file?;
-            let header = file.header();
-            let size = match header.size()?.file_size(options::BINARY) {
-                Ok(size) => size,
-                Err(err) => {
-                    return Err(anyhow!(err));
+    let mut groups = groups
+        .into_iter()
+        .map(|((_, hash), paths)| (hash, paths))
+        .filter(|(_, paths)| paths.len() > 1)
+        .map(|(hash, mut paths)| {
+            paths.sort_unstable();
+            (hash, paths)
+        })
+        .collect::<Vec<_>>();
+    groups.sort_unstable_by(|a, b| a.1.cmp(&b.1));
+
+    for (hash, paths) in groups {
+        print(&format!("{:016x}", hash));
+        for path in paths {
+            print(&format!("  {}", path));
+        }
+    }
+}
+
+/// Print the number of regular files with each file extension and
+/// their total size, largest total first.
+#[throws]
+fn list_extensions_impl<R: Read, P: FnMut(&str)>(
+    archive: &mut Archive<R>,
+    precision: usize,
+    mut print: P,
+) {
+    // Maps extension to file count and total size
+    let mut groups: BTreeMap<String, (u64, u64)> = BTreeMap::new();
+    for file in archive.entries()? {
+        let file = file?;
+        let header = file.header();
+        if !header.entry_type().is_file() {
+            continue;
+        }
+        let extension = match header.path()?.extension() {
+            Some(extension) => format!(".{}", extension.to_string_lossy()),
+            None => "(none)".to_string(),
+        };
+        let group = groups.entry(extension).or_default();
+        group.0 += 1;
+        group.1 += header.size()?;
+    }
+
+    let mut groups = groups.into_iter().collect::<Vec<_>>();
+    // Stable sort, so equal sizes stay in extension order
+    groups.sort_by_key(|(_, (_, size))| std::cmp::Reverse(*size));
+
+    for (extension, (count, size)) in groups {
+        print(&format!(
+            "{}: {} {}, {}",
+            extension,
+            count,
+            if count == 1 { "file" } else { "files" },
+            format_size_with_precision(size, precision)?
+        ));
+    }
+}
+
+/// Bytes read from the start of each file to estimate compressibility.
+const ENTROPY_SAMPLE_SIZE: u64 = 64 * 1024;
+
+/// Estimate how well `data` would compress, from 0 (not at all) to 1,
+/// as one minus its byte entropy relative to the 8 bits per byte of
+/// random data. This ignores repetition longer than a byte, so it's
+/// only a rough guide.
+fn estimate_compressibility(data: &[u8]) -> f64 {
+    let mut counts = [0u64; 256];
+    for byte in data {
+        counts[*byte as usize] += 1;
+    }
+    let len = data.len() as f64;
+    let entropy: f64 = counts
+        .iter()
+        .filter(|count| **count > 0)
+        .map(|count| {
+            let p = *count as f64 / len;
+            -p * p.log2()
+        })
+        .sum();
+    1.0 - entropy / 8.0
+}
+
+/// Print the average estimated compressibility of the files under
+/// each top-level directory, most compressible first. Files at the top
+/// level are grouped under ".".
+#[throws]
+fn list_compressibility_impl<R: Read, P: FnMut(&str)>(
+    archive: &mut Archive<R>,
+    mut print: P,
+) {
+    // Maps directory to file count and sum of compressibility
+    let mut groups: BTreeMap<String, (u64, f64)> = BTreeMap::new();
+    for file in archive.entries()? {
+        let mut file = file?;
+        if !file.header().entry_type().is_file() {
+            continue;
+        }
+        let path = file.path()?.into_owned();
+        let mut components = path.components().filter_map(|c| match c {
+            Component::Normal(name) => Some(name),
+            _ => None,
+        });
+        let first = components.next();
+        let dir = match (first, components.next()) {
+            (Some(dir), Some(_)) => format!("{}/", dir.to_string_lossy()),
+            _ => ".".to_string(),
+        };
+
+        let mut sample = Vec::new();
+        (&mut file)
+            .take(ENTROPY_SAMPLE_SIZE)
+            .read_to_end(&mut sample)?;
+        // Empty files don't tell us anything
+        if sample.is_empty() {
+            continue;
+        }
+        let group = groups.entry(dir).or_default();
+        group.0 += 1;
+        group.1 += estimate_compressibility(&sample);
+    }
+
+    let mut groups = groups
+        .into_iter()
+        .map(|(dir, (count, total))| (dir, count, total / count as f64))
+        .collect::<Vec<_>>();
+    // Stable sort, so equal scores stay in directory order
+    groups.sort_by(|a, b| b.2.total_cmp(&a.2));
+
+    for (dir, count, score) in groups {
+        print(&format!(
+            "{}: {:.0}% compressible ({} {})",
+            dir,
+            score * 100.0,
+            count,
+            if count == 1 { "file" } else { "files" }
+        ));
+    }
+}
+
+/// Get a short name for the type of an entry.
+fn entry_type_name(entry_type: tar::EntryType) -> &'static str {
+    if entry_type.is_file() {
+        "file"
+    } else if entry_type.is_dir() {
+        "dir"
+    } else if entry_type.is_symlink() {
+        "symlink"
+    } else if entry_type.is_hard_link() {
+        "link"
+    } else {
+        "other"
+    }
+}
+
+/// Quote `field` for a CSV file if needed: fields containing commas,
+/// quotes, or line breaks are wrapped in quotes, with any quotes
+/// doubled.
+fn csv_field(field: &str) -> Cow<'_, str> {
+    if field.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+/// What `list --resolve-internal-links` needs to know about an entry.
+struct LinkEntry {
+    entry_type: tar::EntryType,
+    size: u64,
+    /// Target if the entry is a symlink
+    target: Option<PathBuf>,
+}
+
+/// Maximum number of symlinks followed when resolving a link, so that
+/// loops end.
+const MAX_LINK_HOPS: usize = 40;
+
+/// Get the path within the archive that a symlink at `path` pointing
+/// to `target` refers to, or `None` if it points outside the archive.
+fn resolve_link_target(path: &Path, target: &Path) -> Option<PathBuf> {
+    let mut resolved = entry_destination(Path::new(""), path)?;
+    resolved.pop();
+    for component in target.components() {
+        match component {
+            Component::Prefix(..) | Component::RootDir => return None,
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !resolved.pop() {
+                    return None;
                 }
-            };
+            }
+            Component::Normal(part) => resolved.push(part),
+        }
+    }
+    Some(resolved)
+}
+
+/// Describe what the symlink at `path` pointing to `target` refers to,
+/// following any further symlinks within the archive. `entries` holds
+/// every entry in the archive, keyed by normalized path.
+#[throws]
+fn describe_internal_link(
+    path: &Path,
+    target: &Path,
+    entries: &HashMap<PathBuf, LinkEntry>,
+    precision: usize,
+) -> String {
+    let mut path = path.to_path_buf();
+    let mut target = target.to_path_buf();
+    for _ in 0..MAX_LINK_HOPS {
+        let resolved = match resolve_link_target(&path, &target) {
+            Some(resolved) => resolved,
+            None => return "external".to_string(),
+        };
+        let entry = match entries.get(&resolved) {
+            Some(entry) => entry,
+            None => return "missing".to_string(),
+        };
+        if let Some(next) = &entry.target {
+            path = resolved;
+            target = next.clone();
+            continue;
+        }
+        return if entry.entry_type.is_dir() {
+            "dir".to_string()
+        } else if entry.entry_type.is_file() {
+            format!(
+                "file, {}",
+                format_size_with_precision(entry.size, precision)?
+            )
+        } else {
+            "other".to_string()
+        };
+    }
+    "too many links".to_string()
+}
+
+/// Format a symlink target and its description for the end of a
+/// `list` line.
+fn format_link_suffix(
+    (target, description): &(PathBuf, Option<String>),
+) -> String {
+    match description {
+        Some(description) => {
+            format!(" -> {} ({})", target.display(), description)
+        }
+        None => format!(" -> {}", target.display()),
+    }
+}
+
+/// Print the contents of the metadata entry written by `pack`.
+#[throws]
+fn list_meta_impl<R: Read, P: FnMut(&str)>(
+    archive: &mut Archive<R>,
+    mut print: P,
+) {
+    for file in archive.entries()? {
+        let mut file = file?;
+        // The entry is either at the top level or in the directory
+        // from pack --single-root
+        let path = file.path()?;
+        if path.file_name() != Some(OsStr::new(pack::META_FILE_NAME))
+            || path.components().count() > 2
+        {
+            continue;
+        }
+        let mut meta = String::new();
+        file.read_to_string(&mut meta)?;
+        for line in meta.lines() {
+            print(line);
+        }
+        return;
+    }
+    print("no metadata");
+}
+
+/// Print the number of entries, the size of the tarball itself
+/// (`stored_size`), the total size of the entries once unpacked, and
+/// the ratio between the two.
+///
+/// Only the headers are needed for this. Entry contents are skipped
+/// rather than processed, but tarballs have no index, so the skipped
+/// bytes still have to be read from `archive` to find the next header.
+/// The version of the tar crate in use can't seek past them.
+#[throws]
+fn list_summary_impl<R: Read, P: FnMut(&str)>(
+    archive: &mut Archive<R>,
+    stored_size: u64,
+    precision: usize,
+    mut print: P,
+) {
+    let mut count = 0;
+    let mut extracted_size = 0;
+    for file in archive.entries()? {
+        count += 1;
+        extracted_size += file?.header().size()?;
+    }
+
+    print(&format!("entries:   {}", count));
+    print(&format!(
+        "stored:    {}",
+        format_size_with_precision(stored_size, precision)?
+    ));
+    print(&format!(
+        "extracted: {}",
+        format_size_with_precision(extracted_size, precision)?
+    ));
+    if stored_size > 0 {
+        let ratio = extracted_size as f64 / stored_size as f64;
+        print(&format!("ratio:     {:.2}x", ratio));
+    }
+}
+
+/// Order two paths so that, within each directory, subdirectories
+/// come before files, with names in order within each group. `a_is_dir`
+/// and `b_is_dir` say whether each path is itself a directory.
+fn compare_dirs_first(
+    a: &Path,
+    a_is_dir: bool,
+    b: &Path,
+    b_is_dir: bool,
+) -> Ordering {
+    let a_parts = a.components().collect::<Vec<_>>();
+    let b_parts = b.components().collect::<Vec<_>>();
+    let common = a_parts
+        .iter()
+        .zip(&b_parts)
+        .take_while(|(a, b)| a == b)
+        .count();
+    match (a_parts.get(common), b_parts.get(common)) {
+        (Some(a_part), Some(b_part)) => {
+            // The differing component is a directory if anything comes
+            // after it, or if it's the last one and a directory entry
+            let a_dir = common + 1 < a_parts.len() || a_is_dir;
+            let b_dir = common + 1 < b_parts.len() || b_is_dir;
+            b_dir.cmp(&a_dir).then_with(|| a_part.cmp(b_part))
+        }
+        // A directory comes before everything in it
+        _ => a_parts.len().cmp(&b_parts.len()),
+    }
+}
+
+#[throws]
+fn list_tarball_impl<R: Read, P: FnMut(&str)>(
+    archive: &mut Archive<R>,
+    list: &ListCommand,
+    warnings: &mut Warnings,
+    mut print: P,
+) {
+    let precision = list.precision.unwrap_or(DEFAULT_SIZE_PRECISION);
+    if list.find_dupes {
+        return list_duplicates_impl(archive, print)?;
+    }
+    if list.by_extension {
+        return list_extensions_impl(archive, precision, print)?;
+    }
+    if list.show_meta {
+        return list_meta_impl(archive, print)?;
+    }
+    if list.compressibility_by_dir {
+        return list_compressibility_impl(archive, print)?;
+    }
+
+    struct Entry {
+        path: String,
+        /// Formatted size, or None if sizes aren't being shown
+        size: Option<String>,
+        /// Size in bytes
+        bytes: u64,
+        /// Modification time, with --format csv
+        mtime: Option<u64>,
+        /// Content class of regular files with --classify-content
+        class: Option<&'static str>,
+        /// Estimated compressibility of regular files with --sort
+        /// compressibility
+        compressibility: Option<f64>,
+        /// Symlink target, and with --resolve-internal-links a
+        /// description of what it points to
+        link: Option<(PathBuf, Option<String>)>,
+        /// Permission bits, including setuid, setgid, and sticky, if
+        /// filtering by mode or with --format csv
+        mode: Option<u32>,
+        /// Where unpack would put the entry with --as-extracted, or
+        /// None if it would skip it
+        extracted: Option<PathBuf>,
+        entry_type: &'static str,
+        is_dir: bool,
+    }
+
+    let mut seen = HashSet::new();
+    let mut link_entries = HashMap::new();
+    let mut entries = archive
+        .entries()?
+        .take(list.limit.unwrap_or(usize::MAX))
+        .map(|file| -> Result<Entry, Error> {
+            let mut file = file?;
+            let by_compressibility = list.sort == ListSort::Compressibility;
+            let (class, compressibility) = if (list.classify_content
+                || by_compressibility)
+                && file.header().entry_type().is_file()
+            {
+                let sample_size = if by_compressibility {
+                    ENTROPY_SAMPLE_SIZE
+                } else {
+                    CLASSIFY_SAMPLE_SIZE
+                };
+                let mut sample = Vec::new();
+                (&mut file).take(sample_size).read_to_end(&mut sample)?;
+                let class_sample =
+                    &sample[..sample.len().min(CLASSIFY_SAMPLE_SIZE as usize)];
+                (
+                    Some(classify_content(class_sample))
+                        .filter(|_| list.classify_content),
+                    Some(estimate_compressibility(&sample))
+                        .filter(|_| by_compressibility),
+                )
+            } else {
+                (None, None)
+            };
+            let header = file.header();
+            let size = if list.names_only {
+                None
+            } else if list.blocks {
+                let size = header.size()?;
+                Some(format!(
+                    "{} ({} blocks)",
+                    format_size_with_precision(size, precision)?,
+                    entry_blocks(size)
+                ))
+            } else {
+                Some(format_size_with_precision(header.size()?, precision)?)
+            };
+
+            let path = header.path()?;
+            if !seen.insert(path.to_path_buf()) {
+                warnings.warn(format!(
+                    "duplicate path in tarball: {}",
+                    path.display()
+                ));
+            }
+            // Only read the mode and mtime if needed, since some
+            // tarballs leave them empty
+            let csv = list.format == ListFormat::Csv;
+            let mode =
+                if list.mode_mask.is_some() || list.mode_exact.is_some() || csv
+                {
+                    Some(header.mode()? & 0o7777)
+                } else {
+                    None
+                };
+            let mtime = if csv { Some(header.mtime()?) } else { None };
+            let link = if list.resolve_internal_links
+                && header.entry_type().is_symlink()
+            {
+                file.link_name()?.map(|target| (target.into_owned(), None))
+            } else {
+                None
+            };
+            if list.resolve_internal_links {
+                if let Some(key) = entry_destination(Path::new(""), &path) {
+                    let info = LinkEntry {
+                        entry_type: header.entry_type(),
+                        size: header.size()?,
+                        target: link.as_ref().map(|(target, _)| target.clone()),
+                    };
+                    link_entries.insert(key, info);
+                }
+            }
+            let entry_type = entry_type_name(header.entry_type());
+            let is_dir = header.entry_type().is_dir();
+            let path = path.display().to_string();
+
+            Ok(Entry {
+                path,
+                size,
+                bytes: header.size()?,
+                mtime,
+                class,
+                compressibility,
+                link,
+                mode,
+                extracted: None,
+                entry_type,
+                is_dir,
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if list.as_extracted {
+        // Find where each entry goes before any are filtered out, since
+        // they all affect where unpack puts them
+        let destination = list.directory.clone().unwrap_or_default();
+        let paths = entries
+            .iter()
+            .map(|entry| PathBuf::from(&entry.path))
+            .collect::<Vec<_>>();
+        let targets = extracted_paths(&paths, &list.tarball, &destination);
+        for (entry, target) in entries.iter_mut().zip(targets) {
+            entry.extracted = target;
+        }
+    }
+
+    if let Some(destination) = &list.conflicts_with {
+        // Check every entry's path before any are filtered out, since
+        // they all affect where unpack puts them
+        let paths = entries
+            .iter()
+            .map(|entry| PathBuf::from(&entry.path))
+            .collect::<Vec<_>>();
+        let targets = extracted_paths(&paths, &list.tarball, destination);
+        entries = entries
+            .into_iter()
+            .zip(targets)
+            .filter(|(_, target)| {
+                target
+                    .as_ref()
+                    .is_some_and(|target| fs::symlink_metadata(target).is_ok())
+            })
+            .map(|(entry, _)| entry)
+            .collect();
+    }
+    // Result of each filter that's set
+    let filter_results = |entry: &Entry| {
+        [
+            list.mode_mask
+                .map(|mask| entry.mode.is_some_and(|m| m & mask == mask)),
+            list.mode_exact.map(|mode| entry.mode == Some(mode)),
+            list.entry_type.map(|t| entry.entry_type == t),
+            list.match_pattern
+                .as_ref()
+                .map(|pattern| path_matches(pattern, &entry.path)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>()
+    };
+    entries.retain(|entry| {
+        let results = filter_results(entry);
+        match list.filter_logic {
+            FilterLogic::And => results.iter().all(|passed| *passed),
+            FilterLogic::Or => {
+                results.is_empty() || results.iter().any(|passed| *passed)
+            }
+        }
+    });
+
+    for entry in &mut entries {
+        if let Some((target, description)) = &mut entry.link {
+            *description = Some(describe_internal_link(
+                Path::new(&entry.path),
+                target,
+                &link_entries,
+                precision,
+            )?);
+        }
+    }
+
+    if list.as_extracted {
+        entries = entries
+            .into_iter()
+            .filter_map(|mut entry| {
+                entry.path = entry.extracted.take()?.display().to_string();
+                Some(entry)
+            })
+            .collect();
+    }
+
+    if let Some(base) = &list.relative_to {
+        entries = entries
+            .into_iter()
+            .filter_map(|mut entry| {
+                let path = Path::new(&entry.path).strip_prefix(base).ok()?;
+                // Skip the base directory itself
+                if path.as_os_str().is_empty() {
+                    return None;
+                }
+                entry.path = path.display().to_string();
+                Some(entry)
+            })
+            .collect();
+    }
+
+    if list.dirs_first {
+        entries.sort_unstable_by(|a, b| {
+            compare_dirs_first(
+                Path::new(&a.path),
+                a.is_dir,
+                Path::new(&b.path),
+                b.is_dir,
+            )
+        });
+    } else {
+        entries.sort_unstable_by_key(|e| e.path.clone());
+    }
+    if list.sort == ListSort::Compressibility {
+        // Stable sort, so equal scores stay in path order. Entries that
+        // aren't files go last.
+        entries.sort_by(|a, b| {
+            let a = a.compressibility.unwrap_or(f64::NEG_INFINITY);
+            let b = b.compressibility.unwrap_or(f64::NEG_INFINITY);
+            b.total_cmp(&a)
+        });
+    }
+
+    if list.format == ListFormat::Csv {
+        print("path,size,mode,mtime,type");
+        for entry in entries {
+            // OK to unwrap: the mode and mtime are read for CSV
+            print(&format!(
+                "{},{},{:04o},{},{}",
+                csv_field(&entry.path),
+                entry.bytes,
+                entry.mode.unwrap(),
+                entry.mtime.unwrap(),
+                entry.entry_type
+            ));
+        }
+        return;
+    }
+
+    let mut max_path_columns =
+        entries.iter().map(|e| e.path.width()).max().unwrap_or(0);
+    if let Some(width) = list.width {
+        // Columns taken up by everything after the path
+        let max_rest_columns = entries
+            .iter()
+            .map(|e| {
+                e.size.as_ref().map_or(0, |size| size.width() + 1)
+                    + e.class.map_or(0, |class| class.len() + 3)
+                    + e.link
+                        .as_ref()
+                        .map_or(0, |link| format_link_suffix(link).width())
+            })
+            .max()
+            .unwrap_or(0);
+        max_path_columns =
+            max_path_columns.min(width.saturating_sub(max_rest_columns));
+    }
+
+    for entry in entries {
+        let path = truncate_path(&entry.path, max_path_columns);
+        let mut line = match entry.size {
+            Some(size) => format!(
+                "{:path_width$} {}",
+                path,
+                size,
+                path_width = max_path_columns
+            ),
+            None => path.into_owned(),
+        };
+        if let Some(class) = entry.class {
+            line += &format!(" [{}]", class);
+        }
+        if let Some(link) = &entry.link {
+            line += &format_link_suffix(link);
+        }
+        print(&line);
+    }
+}
+
+#[throws]
+fn list_tarball(
+    mut list: ListCommand,
+    timeout: Option<Duration>,
+    warnings: &mut Warnings,
+) {
+    // TODO: decompression
+    let stored_size = fs::metadata(&list.tarball)?.len();
+    let mut archive = Archive::new(open_tarball(&list.tarball, timeout)?);
+
+    let cwd = env::current_dir()?;
+    list.directory = Some(match &list.directory {
+        Some(dir) => cwd.join(dir),
+        None => cwd,
+    });
+
+    if list.summary {
+        let precision = list.precision.unwrap_or(DEFAULT_SIZE_PRECISION);
+        list_summary_impl(&mut archive, stored_size, precision, print_str)?;
+    } else {
+        list_tarball_impl(&mut archive, &list, warnings, print_str)?;
+    }
+}
+
+/// Path that means stdin or stdout rather than a file.
+const STDIO_PATH: &str = "-";
+
+/// Box `reader`, making reads fail if they make no progress within
+/// `timeout` if it's set.
+fn with_timeout<R: Read + Send + 'static>(
+    reader: R,
+    timeout: Option<Duration>,
+) -> Box<dyn Read> {
+    match timeout {
+        Some(timeout) => Box::new(TimeoutReader::new(reader, timeout)),
+        None => Box::new(reader),
+    }
+}
+
+/// Open the tarball at `path` for reading. If `timeout` is set, reads
+/// fail if they make no progress within that time.
+#[throws]
+fn open_tarball(path: &Path, timeout: Option<Duration>) -> Box<dyn Read> {
+    with_timeout(File::open(path)?, timeout)
+}
+
+/// Write the contents of each regular file in `archive` to `output`,
+/// with `separator` and a newline between files if it's set.
+#[throws]
+fn cat_all_impl<R: Read, W: Write>(
+    archive: &mut Archive<R>,
+    separator: Option<&str>,
+    mut output: W,
+) {
+    let mut first = true;
+    for file in archive.entries()? {
+        let mut file = file?;
+        if !file.header().entry_type().is_file() {
+            continue;
+        }
+        if let (Some(separator), false) = (separator, first) {
+            writeln!(output, "{}", separator)?;
+        }
+        first = false;
+        io::copy(&mut file, &mut output)?;
+    }
+}
+
+#[throws]
+fn cat_all(cat: CatAllCommand, timeout: Option<Duration>) {
+    let mut archive = Archive::new(open_tarball(&cat.tarball, timeout)?);
+    let stdout = io::stdout();
+    cat_all_impl(&mut archive, cat.separator.as_deref(), stdout.lock())?;
+}
+
+/// Check that the metadata of `entry` doesn't vary between builds,
+/// returning a description of the first problem found.
+#[throws]
+fn check_reproducible_entry<R: Read>(entry: &mut Entry<R>) -> Option<String> {
+    let header = entry.header();
+    if header.mtime()? != 0 {
+        return Some("mtime is not zero".to_string());
+    }
+    if header.uid()? != 0 || header.gid()? != 0 {
+        return Some("uid or gid is not zero".to_string());
+    }
+    if header.username_bytes().is_some_and(|name| !name.is_empty())
+        || header
+            .groupname_bytes()
+            .is_some_and(|name| !name.is_empty())
+    {
+        return Some("user or group name is set".to_string());
+    }
+    if let Some(extensions) = entry.pax_extensions()? {
+        for extension in extensions {
+            let key = extension?.key()?;
+            if key == "atime" || key == "ctime" || key == "mtime" {
+                return Some(format!("pax header contains {}", key));
+            }
+        }
+    }
+    None
+}
+
+#[throws]
+fn check_tarball_impl<R: Read, P: FnMut(&str)>(
+    archive: &mut Archive<R>,
+    check: &CheckCommand,
+    mut print: P,
+) {
+    let mut prev_path: Option<Vec<u8>> = None;
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path_bytes().into_owned();
+        let display_path = String::from_utf8_lossy(&path).into_owned();
+
+        if check.reproducible {
+            if let Some(prev_path) = &prev_path {
+                if path < *prev_path {
+                    throw!(anyhow!(
+                        "not reproducible: {}: not sorted, comes after {}",
+                        display_path,
+                        String::from_utf8_lossy(prev_path)
+                    ));
+                }
+            }
+            if let Some(problem) = check_reproducible_entry(&mut entry)? {
+                throw!(anyhow!(
+                    "not reproducible: {}: {}",
+                    display_path,
+                    problem
+                ));
+            }
+        }
+
+        // Read the contents to make sure the whole tarball is valid
+        let size = io::copy(&mut entry, &mut io::sink())?;
+        if check.size_mismatch {
+            let header_size = entry.header().size()?;
+            if size != header_size {
+                throw!(anyhow!(
+                    "size mismatch: {}: header says {} bytes but {} are \
+                     stored",
+                    display_path,
+                    header_size,
+                    size
+                ));
+            }
+        }
+        prev_path = Some(path);
+    }
+    print("ok");
+}
+
+#[throws]
+fn check_tarball(check: CheckCommand, timeout: Option<Duration>) {
+    let mut archive = Archive::new(open_tarball(&check.tarball, timeout)?);
+
+    check_tarball_impl(&mut archive, &check, print_str)?;
+}
+
+/// This is similar to Path::file_stem, but it additionally strips off
+/// the ".tar" extension if that is present behind the first
+/// extension.
+///
+/// Examples:
+///
+///     foo.tar.gz -> foo
+///     foo.tar -> foo
+///     foo -> foo
+///     foo.bar.gz -> foo.bar
+fn file_stem(path: &Path) -> Option<&OsStr> {
+    let stem = path.file_stem()?;
+    let stem_path = Path::new(stem);
+    if stem_path.extension() == Some(OsStr::new("tar")) {
+        stem_path.file_stem()
+    } else {
+        Some(stem)
+    }
+}
+
+enum DirContents {
+    /// Directory is empty
+    Empty,
+    /// Directory contains only one child (either file or directory)
+    One(PathBuf),
+    /// Directory contains multiple children (files or directories)
+    Multiple,
+}
+
+impl DirContents {
+    #[throws]
+    fn new(dir: &Path) -> DirContents {
+        // Check if there's more than one file in the temporary directory
+        let mut first_file = None;
+        let mut count = 0;
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            count += 1;
+            if first_file.is_none() {
+                first_file = Some(entry.path());
+            } else {
+                break;
+            }
+        }
+
+        if count == 0 {
+            DirContents::Empty
+        } else if count == 1 {
+            // OK to unwrap: first_file has been set if count > 0
+            DirContents::One(first_file.unwrap())
+        } else {
+            DirContents::Multiple
+        }
+    }
+}
+
+/// Get the path that an entry with path `entry_path` is unpacked to
+/// within `dir`.
+///
+/// This follows the same rules as `Entry::unpack_in`: root and `.`
+/// components are dropped, and `None` is returned for paths containing
+/// `..` or that are empty, since those entries are skipped.
+fn entry_destination(dir: &Path, entry_path: &Path) -> Option<PathBuf> {
+    let mut dst = dir.to_path_buf();
+    for component in entry_path.components() {
+        match component {
+            Component::Prefix(..) | Component::RootDir | Component::CurDir => {
+                continue
+            }
+            Component::ParentDir => return None,
+            Component::Normal(part) => dst.push(part),
+        }
+    }
+    if dst == dir {
+        None
+    } else {
+        Some(dst)
+    }
+}
+
+/// Get the directory that entries are placed in when unpacking the
+/// tarball `source` into `destination`, given the paths the entries
+/// are unpacked to relative to that directory.
+///
+/// If all entries share a single top-level item, they are placed
+/// directly in `destination`, otherwise they are placed in a new
+/// directory named after the tarball.
+fn extraction_dir<'a>(
+    relative_paths: impl Iterator<Item = &'a Path>,
+    source: &Path,
+    destination: &Path,
+) -> PathBuf {
+    let top_level = relative_paths
+        .filter_map(|path| path.components().next())
+        .collect::<HashSet<_>>();
+    if top_level.len() > 1 {
+        // OK to unwrap: see unpack_tarball_impl
+        destination.join(file_stem(source).unwrap())
+    } else {
+        destination.to_path_buf()
+    }
+}
+
+/// Get the paths that entries with paths `entry_paths` are unpacked
+/// to when unpacking the tarball `source` into `destination`.
+///
+/// This predicts the result of `unpack_tarball_impl` without touching
+/// the filesystem. Entries that are skipped when unpacking get `None`.
+fn extracted_paths(
+    entry_paths: &[PathBuf],
+    source: &Path,
+    destination: &Path,
+) -> Vec<Option<PathBuf>> {
+    let relative_paths = entry_paths
+        .iter()
+        .map(|path| entry_destination(Path::new(""), path))
+        .collect::<Vec<_>>();
+
+    let root = extraction_dir(
+        relative_paths.iter().flatten().map(PathBuf::as_path),
+        source,
+        destination,
+    );
+
+    relative_paths
+        .into_iter()
+        .map(|path| Some(root.join(path?)))
+        .collect()
+}
+
+/// Get ready to unpack an entry to `path` within `dir` without going
+/// through `Entry::unpack_in`, by creating its parent directories and
+/// removing anything other than a directory already at `path`.
+#[throws]
+fn prepare_unpack_path(dir: &Path, path: &Path) {
+    // OK to unwrap: entry_destination always returns a child of dir
+    let parent = path.parent().unwrap();
+    fs::create_dir_all(parent)?;
+    // Same check as Entry::unpack_in: don't let a symlink unpacked by
+    // an earlier entry redirect this entry outside of dir
+    if !parent.canonicalize()?.starts_with(dir.canonicalize()?) {
+        throw!(anyhow!(
+            "{} is outside of {}",
+            path.display(),
+            dir.display()
+        ));
+    }
+    // Replace anything already at this path rather than writing
+    // through it
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        if !metadata.is_dir() {
+            fs::remove_file(path)?;
+        }
+    }
+}
+
+/// Unpack an entry to `path` within `dir`, where `path` has been
+/// changed by `transform` and so can't be unpacked with
+/// `Entry::unpack_in`.
+#[throws]
+fn unpack_transformed<R: Read>(
+    entry: &mut Entry<R>,
+    dir: &Path,
+    path: &Path,
+    transform: &PathTransform,
+) {
+    prepare_unpack_path(dir, path)?;
+
+    if entry.header().entry_type().is_hard_link() {
+        // Entry::unpack would look for the link target relative to the
+        // current directory, and with the original path, so link it
+        // here instead
+        let target = entry
+            .link_name()?
+            .ok_or_else(|| anyhow!("hard link has no target"))?;
+        let target = transform
+            .apply(&target)
+            .and_then(|target| entry_destination(dir, &target))
+            .ok_or_else(|| {
+                anyhow!(
+                    "hard link target {} was not unpacked",
+                    target.display()
+                )
+            })?;
+        fs::hard_link(target, path)?;
+    } else {
+        entry.unpack(path)?;
+    }
+}
+
+/// Unpack a regular file entry to `path` within `dir`, then read the
+/// file back and check that it matches what was in the tarball.
+#[throws]
+fn unpack_file_verified<R: Read>(
+    entry: &mut Entry<R>,
+    dir: &Path,
+    path: &Path,
+    preserve_mtime: bool,
+) {
+    prepare_unpack_path(dir, path)?;
+
+    let mut writer = HashWriter::new(File::create(path)?);
+    io::copy(entry, &mut writer)?;
+    let expected = writer.hasher.finish();
+    drop(writer);
+
+    if preserve_mtime {
+        let mtime = FileTime::from_unix_time(entry.header().mtime()? as i64, 0);
+        filetime::set_file_mtime(path, mtime)?;
+    }
+
+    if hash_reader(File::open(path)?)? != expected {
+        throw!(anyhow!(
+            "verification failed: {} does not match the tarball",
+            path.display()
+        ));
+    }
+}
+
+/// Umask read at startup, for systems where it can't be read without
+/// changing it. See `current_umask`.
+#[cfg(unix)]
+static STARTUP_UMASK: OnceLock<u32> = OnceLock::new();
+
+/// Read the umask by setting it and then putting it back.
+///
+/// This briefly changes the umask for the whole process, so it's only
+/// safe to call before any other threads are started.
+#[cfg(unix)]
+fn swap_umask() -> u32 {
+    unsafe {
+        let umask = libc::umask(0);
+        libc::umask(umask);
+        umask as u32
+    }
+}
+
+/// Read the umask from /proc, which doesn't change it.
+#[cfg(unix)]
+fn proc_umask() -> Option<u32> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let umask = status
+        .lines()
+        .find_map(|line| line.strip_prefix("Umask:"))?;
+    u32::from_str_radix(umask.trim(), 8).ok()
+}
+
+/// Get the process's file mode creation mask.
+///
+/// This is read from /proc where possible. Elsewhere it's the umask
+/// that `main` read before starting any threads, since reading it
+/// means setting it, which races with threads creating files.
+#[cfg(unix)]
+fn current_umask() -> Option<u32> {
+    proc_umask().or_else(|| Some(*STARTUP_UMASK.get_or_init(swap_umask)))
+}
+
+#[cfg(not(unix))]
+fn current_umask() -> Option<u32> {
+    None
+}
+
+/// Set the permissions of an entry unpacked to `path` from the mode in
+/// its header.
+///
+/// If `umask` is set it's applied to the mode, which is what tar does
+/// by default. Otherwise the mode is used as-is, including the
+/// setuid, setgid, and sticky bits.
+#[cfg(unix)]
+#[throws]
+fn set_unpacked_mode<R: Read>(
+    entry: &Entry<R>,
+    path: &Path,
+    umask: Option<u32>,
+) {
+    use std::os::unix::fs::PermissionsExt;
+
+    // Symlinks don't have meaningful permissions, and hard links share
+    // the permissions of their target
+    let entry_type = entry.header().entry_type();
+    if !entry_type.is_file() && !entry_type.is_dir() {
+        return;
+    }
+
+    let mode = entry.header().mode()?;
+    let mode = match umask {
+        Some(umask) => mode & 0o777 & !umask,
+        None => mode & 0o7777,
+    };
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+}
+
+#[cfg(not(unix))]
+#[throws]
+fn set_unpacked_mode<R: Read>(
+    _entry: &Entry<R>,
+    _path: &Path,
+    _umask: Option<u32>,
+) {
+}
+
+/// Set the permissions of a directory created by unpack to `mode`, if
+/// set.
+#[cfg(unix)]
+#[throws]
+fn set_dir_mode(path: &Path, mode: Option<u32>) {
+    use std::os::unix::fs::PermissionsExt;
+
+    if let Some(mode) = mode {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    }
+}
+
+#[cfg(not(unix))]
+#[throws]
+fn set_dir_mode(_path: &Path, _mode: Option<u32>) {}
+
+/// What `unpack_entries` did.
+#[derive(Debug, Default)]
+struct UnpackedEntries {
+    /// Paths of the regular files that were unpacked, relative to the
+    /// directory they were unpacked in.
+    files: Vec<PathBuf>,
+    /// Number of entries that were skipped rather than unpacked.
+    skipped: usize,
+    /// Whether unpacking stopped early because of `--limit`.
+    limited: bool,
+}
+
+/// Results of an unpack, printed with `--summary-json`.
+#[derive(Debug, Default)]
+struct UnpackSummary {
+    /// Path the tarball was unpacked to, or None if it was empty.
+    destination: Option<PathBuf>,
+    /// Number of regular files written.
+    files: usize,
+    /// Total size of the regular files written.
+    bytes: u64,
+    /// Number of entries that weren't unpacked, including files left
+    /// alone by `--only-newer`.
+    skipped: usize,
+}
+
+impl UnpackSummary {
+    /// Summarize an unpack to `destination` that wrote the files in
+    /// `paths` and skipped `skipped` entries.
+    #[throws]
+    fn new(
+        destination: &Path,
+        paths: &[PathBuf],
+        skipped: usize,
+    ) -> UnpackSummary {
+        let mut bytes = 0;
+        for path in paths {
+            bytes += fs::metadata(path)?.len();
+        }
+        UnpackSummary {
+            destination: Some(destination.to_path_buf()),
+            files: paths.len(),
+            bytes,
+            skipped,
+        }
+    }
+
+    /// Format the summary as a single-line JSON object, along with any
+    /// warnings.
+    fn to_json(&self, warnings: &Warnings) -> String {
+        let destination = match &self.destination {
+            Some(path) => json_string(&path.to_string_lossy()),
+            None => "null".to_string(),
+        };
+        let warnings = warnings
+            .messages
+            .iter()
+            .map(|message| json_string(message))
+            .collect::<Vec<_>>();
+        format!(
+            concat!(
+                "{{\"destination\":{},\"files\":{},\"bytes\":{},",
+                "\"skipped\":{},\"warnings\":[{}]}}"
+            ),
+            destination,
+            self.files,
+            self.bytes,
+            self.skipped,
+            warnings.join(",")
+        )
+    }
+}
+
+/// Quote `s` as a JSON string.
+fn json_string(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => quoted += "\\\"",
+            '\\' => quoted += "\\\\",
+            '\n' => quoted += "\\n",
+            '\r' => quoted += "\\r",
+            '\t' => quoted += "\\t",
+            c if c.is_control() => {
+                quoted += &format!("\\u{:04x}", c as u32);
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Longest path that Windows allows without the `\\?\` prefix.
+const WINDOWS_MAX_PATH: usize = 260;
+
+/// Add the `\\?\` prefix to an absolute Windows path, which lifts the
+/// limit on its length.
+#[cfg_attr(not(windows), allow(dead_code))]
+fn add_long_path_prefix(path: &str) -> String {
+    if path.starts_with(r"\\?\") {
+        path.to_string()
+    } else if let Some(unc) = path.strip_prefix(r"\\") {
+        format!(r"\\?\UNC\{}", unc)
+    } else {
+        format!(r"\\?\{}", path)
+    }
+}
+
+/// Get the form of the unpack directory `dir` to use, so that paths
+/// under it can be longer than `WINDOWS_MAX_PATH`.
+#[cfg(windows)]
+fn long_path_dir(dir: &Path) -> PathBuf {
+    match dir.to_str() {
+        Some(s) if dir.is_absolute() => PathBuf::from(add_long_path_prefix(s)),
+        _ => dir.to_path_buf(),
+    }
+}
+
+#[cfg(not(windows))]
+fn long_path_dir(dir: &Path) -> PathBuf {
+    dir.to_path_buf()
+}
+
+/// Describe anything unsafe about the permissions in `mode`, for
+/// `--warn-permissions`.
+fn unsafe_permissions(mode: u32) -> Option<String> {
+    let problems = [
+        (0o4000, "setuid"),
+        (0o2000, "setgid"),
+        (0o002, "world-writable"),
+    ]
+    .iter()
+    .filter(|(bit, _)| mode & bit != 0)
+    .map(|(_, name)| *name)
+    .collect::<Vec<_>>();
+    if problems.is_empty() {
+        None
+    } else {
+        Some(problems.join(", "))
+    }
+}
+
+/// Record of which entries have been unpacked, for `--resume-state`.
+///
+/// The file has a line for each finished entry with its index in the
+/// tarball and its path. Only the index is used; the path is there to
+/// make the file readable.
+struct ResumeState {
+    done: HashSet<usize>,
+    file: File,
+}
+
+impl ResumeState {
+    /// Open the state file at `path`, creating it if it doesn't exist.
+    #[throws]
+    fn open(path: &Path) -> ResumeState {
+        let done = match fs::read_to_string(path) {
+            // Ignore a line cut off partway through, or anything else
+            // that can't be read
+            Ok(contents) => contents
+                .split_inclusive('\n')
+                .filter_map(|line| {
+                    line.strip_suffix('\n')?.split(' ').next()?.parse().ok()
+                })
+                .collect(),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => HashSet::new(),
+            Err(err) => throw!(err),
+        };
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        ResumeState { done, file }
+    }
+
+    fn is_done(&self, index: usize) -> bool {
+        self.done.contains(&index)
+    }
+
+    #[throws]
+    fn record(&mut self, index: usize, path: &Path) {
+        writeln!(self.file, "{} {}", index, path.display())?;
+        self.file.sync_data()?;
+    }
+}
+
+/// Unpack each entry of `archive` into `dir`.
+///
+/// This does the same thing as `Archive::unpack`, but one entry at a
+/// time so that the options in `unpack` can be applied to each entry.
+#[throws]
+fn unpack_entries<R: Read>(
+    archive: &mut Archive<R>,
+    dir: &Path,
+    unpack: &UnpackCommand,
+    warnings: &mut Warnings,
+) -> UnpackedEntries {
+    // As in Archive::unpack, directories are unpacked last so that
+    // their permissions can't interfere with unpacking their contents
+    archive.set_preserve_mtime(!unpack.mtime_now);
+
+    let dir = &long_path_dir(dir);
+    let mut directories = Vec::new();
+    let mut unpacked_entries = UnpackedEntries::default();
+    let mut seen = HashSet::new();
+    let mut resume = match &unpack.resume_state {
+        Some(path) => Some(ResumeState::open(path)?),
+        None => None,
+    };
+    let umask = if unpack.same_permissions {
+        None
+    } else {
+        current_umask()
+    };
+    let transform = unpack.transform();
+    let unpack_entry =
+        |entry: &mut Entry<R>, path: &Path| -> Result<bool, Error> {
+            if transform.is_identity() {
+                Ok(entry.unpack_in(dir)?)
+            } else {
+                unpack_transformed(entry, dir, path, &transform)?;
+                Ok(true)
+            }
+        };
+
+    for (index, entry) in archive.entries()?.enumerate() {
+        if Some(index) == unpack.limit {
+            unpacked_entries.limited = true;
+            break;
+        }
+        if resume.as_ref().is_some_and(|resume| resume.is_done(index)) {
+            continue;
+        }
+        let mut entry = entry?;
+        let entry_path = entry.path()?.to_path_buf();
+        let transformed_path = match transform.apply(&entry_path) {
+            Some(path) => path,
+            None => {
+                unpacked_entries.skipped += 1;
+                continue;
+            }
+        };
+        let path = match entry_destination(dir, &transformed_path) {
+            Some(path) => path,
+            None => {
+                unpacked_entries.skipped += 1;
+                if entry_path.components().any(|c| c == Component::ParentDir) {
+                    warnings.warn(format!(
+                        "skipped entry with '..' in its path: {}",
+                        entry_path.display()
+                    ));
+                }
+                continue;
+            }
+        };
+
+        if unpack.skip_long_paths && path.as_os_str().len() >= WINDOWS_MAX_PATH
+        {
+            warnings.warn(format!(
+                "skipped entry with a path that's too long: {}",
+                entry_path.display()
+            ));
+            unpacked_entries.skipped += 1;
+            continue;
+        }
+
+        let entry_type = entry.header().entry_type();
+        if !unpack.allows_type(entry_type) {
+            warnings.warn(format!(
+                "skipped {} entry not allowed by --allow-types: {}",
+                entry_type_name(entry_type),
+                entry_path.display()
+            ));
+            unpacked_entries.skipped += 1;
+            continue;
+        }
+        if (unpack.warn_permissions || unpack.strict)
+            && (entry_type.is_file() || entry_type.is_dir())
+        {
+            if let Some(problem) = unsafe_permissions(entry.header().mode()?) {
+                let message = format!(
+                    "entry has unsafe permissions ({}): {}",
+                    problem,
+                    entry_path.display()
+                );
+                if unpack.strict {
+                    throw!(anyhow!(message));
+                }
+                warnings.warn(message);
+            }
+        }
+        if !entry_type.is_dir() && !seen.insert(path.clone()) {
+            warnings.warn(format!(
+                "duplicate path in tarball: {}",
+                entry_path.display()
+            ));
+        }
+
+        if entry_type.is_dir() && !unpack.preserve_order {
+            directories.push((index, entry, path));
+            continue;
+        }
+
+        let unpacked = if unpack.direct && entry_type.is_file() {
+            // Write to a separate path so that an interrupted unpack
+            // can't leave a truncated file with the real name
+            let mut partial = path.clone().into_os_string();
+            partial.push(".partial");
+            let partial = PathBuf::from(partial);
+            if unpack.verify {
+                unpack_file_verified(
+                    &mut entry,
+                    dir,
+                    &partial,
+                    !unpack.mtime_now,
+                )?;
+            } else {
+                prepare_unpack_path(dir, &partial)?;
+                entry.unpack(&partial)?;
+            }
+            fs::rename(&partial, &path)?;
+            true
+        } else if unpack.verify && entry_type.is_file() {
+            unpack_file_verified(&mut entry, dir, &path, !unpack.mtime_now)?;
+            true
+        } else {
+            unpack_entry(&mut entry, &path)?
+        };
+        if unpacked {
+            set_unpacked_mode(&entry, &path, umask)?;
+            if entry_type.is_file() {
+                // OK to unwrap: entry_destination returns a child of dir
+                let path = path.strip_prefix(dir).unwrap().to_path_buf();
+                unpacked_entries.files.push(path);
+            }
+        }
+        if let Some(resume) = &mut resume {
+            resume.record(index, &entry_path)?;
+        }
+    }
+    for (index, mut entry, path) in directories {
+        let unpacked = unpack_entry(&mut entry, &path)?;
+        if unpacked {
+            set_unpacked_mode(&entry, &path, umask)?;
+        }
+        if let Some(resume) = &mut resume {
+            resume.record(index, &entry.path()?)?;
+        }
+    }
+    unpacked_entries
+}
+
+/// Move `src` to `dst`, merging directories into any that already
+/// exist and skipping files where the existing file in `dst` is at
+/// least as new as the one in `src`. The paths of skipped files in
+/// `dst` are added to `skipped`.
+#[throws]
+fn move_newer(src: &Path, dst: &Path, skipped: &mut Vec<PathBuf>) {
+    let src_metadata = fs::symlink_metadata(src)?;
+    let dst_metadata = match fs::symlink_metadata(dst) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            fs::rename(src, dst)?;
+            return;
+        }
+        Err(err) => throw!(err),
+    };
+
+    if src_metadata.is_dir() && dst_metadata.is_dir() {
+        for child in fs::read_dir(src)? {
+            let child = child?;
+            move_newer(&child.path(), &dst.join(child.file_name()), skipped)?;
+        }
+    } else if FileTime::from_last_modification_time(&dst_metadata)
+        >= FileTime::from_last_modification_time(&src_metadata)
+    {
+        skipped.push(dst.to_path_buf());
+    } else {
+        fs::rename(src, dst)?;
+    }
+}
+
+/// Run the `--exec` command for a file unpacked to `path`.
+///
+/// The command is split on whitespace and run directly rather than
+/// through a shell, with `{}` in any argument replaced by the path.
+#[throws]
+fn run_exec(command: &str, path: &Path) {
+    let path = path.to_string_lossy();
+    let mut args = command
+        .split_whitespace()
+        .map(|arg| arg.replace("{}", &path));
+    let program = args
+        .next()
+        .ok_or_else(|| anyhow!("--exec command is empty"))?;
+    let status = process::Command::new(&program).args(args).status()?;
+    if !status.success() {
+        throw!(anyhow!("{} exited with {}", program, status));
+    }
+}
+
+/// Check that none of the existing parts of `path` are symlinks, for
+/// `--no-follow-dest-symlinks`.
+#[throws]
+fn check_no_symlinks(path: &Path) {
+    // Check from the root down, so the error names the first symlink
+    let ancestors = path.ancestors().collect::<Vec<_>>();
+    for ancestor in ancestors.into_iter().rev() {
+        match fs::symlink_metadata(ancestor) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                throw!(anyhow!(
+                    "destination path contains a symlink: {}",
+                    ancestor.display()
+                ));
+            }
+            Ok(_) => {}
+            // Anything that doesn't exist yet can't be a symlink
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return,
+            Err(err) => throw!(err),
+        }
+    }
+}
+
+/// Message printed when unpacking stops after `limit` entries.
+fn limit_message(limit: usize) -> String {
+    format!(
+        "stopped after {} {} due to --limit",
+        limit,
+        if limit == 1 { "entry" } else { "entries" }
+    )
+}
+
+/// Check that the destination directory is usable, creating it first
+/// if `--create-dest` is set.
+#[throws]
+fn prepare_destination(unpack: &UnpackCommand, destination: &Path) {
+    if unpack.no_follow_dest_symlinks {
+        check_no_symlinks(destination)?;
+    }
+    if unpack.create_dest {
+        // Directories that will be created, deepest first
+        let missing = destination
+            .ancestors()
+            .take_while(|dir| !dir.as_os_str().is_empty() && !dir.exists())
+            .collect::<Vec<_>>();
+        fs::create_dir_all(destination)?;
+        for dir in missing {
+            set_dir_mode(dir, unpack.dir_mode)?;
+        }
+    } else if !destination.is_dir() {
+        throw!(anyhow!(
+            "destination directory does not exist: {}",
+            destination.display()
+        ));
+    }
+}
+
+/// Run the `--exec` command, if any, for each file in `paths`.
+#[throws]
+fn run_exec_all(
+    unpack: &UnpackCommand,
+    paths: impl Iterator<Item = PathBuf>,
+    warnings: &mut Warnings,
+) {
+    let command = match &unpack.exec {
+        Some(command) => command,
+        None => return,
+    };
+    for path in paths {
+        if let Err(err) = run_exec(command, &path) {
+            let message =
+                format!("--exec failed for {}: {}", path.display(), err);
+            if unpack.exec_fail_stop {
+                throw!(anyhow!(message));
+            }
+            warnings.warn(message);
+        }
+    }
+}
+
+#[throws]
+fn unpack_tarball_impl<R: Read, P: FnMut(&str)>(
+    archive: &mut Archive<R>,
+    unpack: &UnpackCommand,
+    destination: &Path,
+    warnings: &mut Warnings,
+    mut print: P,
+) {
+    let source = unpack.tarball_name();
+    prepare_destination(unpack, destination)?;
+
+    // Unpack into a temporary directory
+    let tmp_dir = tempfile::Builder::new().tempdir_in(destination)?;
+    let unpacked_entries =
+        unpack_entries(archive, tmp_dir.path(), unpack, warnings)?;
+    if unpacked_entries.limited {
+        // OK to unwrap: only limited if a limit was set
+        print(&limit_message(unpack.limit.unwrap()));
+    }
+
+    // Check if there's more than one file in the temporary directory
+    let (unpacked_path, target_path) = match DirContents::new(tmp_dir.path())? {
+        DirContents::Empty => {
+            if unpack.summary_json {
+                let summary = UnpackSummary {
+                    skipped: unpacked_entries.skipped,
+                    ..Default::default()
+                };
+                print(&summary.to_json(warnings));
+            } else {
+                print("empty tarball");
+            }
+            return;
+        }
+        DirContents::One(path) if !unpack.always_subdir => {
+            // OK to unwrap: this path comes from a directory listing,
+            // we know the path doesn't terminate in "..".
+            let target_path = destination.join(path.file_name().unwrap());
+            (path, target_path)
+        }
+        DirContents::One(_) | DirContents::Multiple => {
+            // OK to unwrap: file_stem can only return None if the input
+            // path has no file component, but since we've already
+            // successfully unpacked the tarball we know the path has a
+            // file name. When reading stdin, parse_stdin_name checks
+            // that --stdin-name has one.
+            let new_dir = destination.join(file_stem(source).unwrap());
+            // TODO: check if the target path already exists and deal with
+            // that in some way
+            set_dir_mode(tmp_dir.path(), unpack.dir_mode)?;
+            (tmp_dir.path().to_path_buf(), new_dir)
+        }
+    };
+
+    let mut skipped = Vec::new();
+    if unpack.only_newer {
+        move_newer(&unpacked_path, &target_path, &mut skipped)?;
+    } else {
+        fs::rename(&unpacked_path, &target_path)?;
+    }
+    if unpack.summary_json {
+        // The summary is printed at the end instead
+    } else if unpack.only_newer {
+        print(&format!(
+            "unpacked to {}, skipped {} up-to-date {}",
+            target_path.display(),
+            skipped.len(),
+            if skipped.len() == 1 { "file" } else { "files" }
+        ));
+    } else {
+        print(&format!("unpacked to {}", target_path.display()));
+    }
+
+    let paths = unpacked_entries
+        .files
+        .iter()
+        .map(|file| {
+            // OK to unwrap: all unpacked files are under unpacked_path
+            let relative_path = tmp_dir
+                .path()
+                .join(file)
+                .strip_prefix(&unpacked_path)
+                .unwrap()
+                .to_path_buf();
+            if relative_path.as_os_str().is_empty() {
+                target_path.clone()
+            } else {
+                target_path.join(relative_path)
+            }
+        })
+        .filter(|path| !skipped.contains(path))
+        .collect::<Vec<_>>();
+    let summary = UnpackSummary::new(
+        &target_path,
+        &paths,
+        unpacked_entries.skipped + skipped.len(),
+    )?;
+    run_exec_all(unpack, paths.into_iter(), warnings)?;
+    if unpack.summary_json {
+        print(&summary.to_json(warnings));
+    }
+}
+
+/// An entry that `plan_unpack` expects to be unpacked.
+struct PlannedEntry {
+    /// Path relative to the directory it's unpacked into
+    path: PathBuf,
+    entry_type: tar::EntryType,
+    size: u64,
+}
+
+/// Where unpack will put the entries of a tarball.
+struct UnpackPlan {
+    /// Entries that will be unpacked, in tarball order
+    entries: Vec<PlannedEntry>,
+    /// Directory the entries will be unpacked into
+    dir: PathBuf,
+}
+
+/// Read the entries of `archive` to find out where unpacking it into
+/// `destination` will put them, without unpacking anything.
+#[throws]
+fn plan_unpack<R: Read>(
+    archive: &mut Archive<R>,
+    unpack: &UnpackCommand,
+    destination: &Path,
+) -> UnpackPlan {
+    let transform = unpack.transform();
+    let mut entries = Vec::new();
+    for entry in archive.entries()?.take(unpack.limit.unwrap_or(usize::MAX)) {
+        let entry = entry?;
+        let entry_type = entry.header().entry_type();
+        if !unpack.allows_type(entry_type) {
+            continue;
+        }
+        let path = match transform
+            .apply(&entry.path()?)
+            .and_then(|path| entry_destination(Path::new(""), &path))
+        {
+            Some(path) => path,
+            None => continue,
+        };
+        entries.push(PlannedEntry {
+            path,
+            entry_type,
+            size: entry.header().size()?,
+        });
+    }
+
+    let dir = if unpack.always_subdir {
+        // OK to unwrap: see unpack_tarball_impl
+        destination.join(file_stem(unpack.tarball_name()).unwrap())
+    } else {
+        extraction_dir(
+            entries.iter().map(|entry| entry.path.as_path()),
+            unpack.tarball_name(),
+            destination,
+        )
+    };
+    UnpackPlan { entries, dir }
+}
+
+/// Print what unpacking `archive` into `destination` would do, for
+/// `--describe`, without unpacking anything.
+#[throws]
+fn describe_unpack_impl<R: Read, P: FnMut(&str)>(
+    archive: &mut Archive<R>,
+    unpack: &UnpackCommand,
+    destination: &Path,
+    mut print: P,
+) {
+    let plan = plan_unpack(archive, unpack, destination)?;
+    let files = plan
+        .entries
+        .iter()
+        .filter(|entry| entry.entry_type.is_file())
+        .collect::<Vec<_>>();
+    let size = files.iter().map(|entry| entry.size).sum();
+    let files = files.len();
+    let single_file = plan.entries.last().is_some_and(|entry| {
+        entry.entry_type.is_file() && entry.path.components().count() == 1
+    });
+    let dir = plan.dir;
+
+    let files = format!(
+        "{} {} ({})",
+        files,
+        if files == 1 { "file" } else { "files" },
+        format_size(size)?
+    );
+    match plan.entries.first().map(|entry| &entry.path) {
+        None => print("empty tarball, nothing to unpack"),
+        Some(_) if dir != destination => {
+            let new = if dir.exists() { "" } else { ", creating it" };
+            print(&format!(
+                "will unpack {} into {}{}",
+                files,
+                dir.display(),
+                new
+            ));
+        }
+        // A single file is placed directly in the destination
+        Some(_) if single_file => {
+            let destination = if unpack.directory.is_none() {
+                "the current directory".to_string()
+            } else {
+                destination.display().to_string()
+            };
+            print(&format!("will place {} in {}", files, destination));
+        }
+        Some(path) => {
+            // OK to unwrap: entry_destination never returns an empty
+            // path
+            let top = destination.join(path.components().next().unwrap());
+            print(&format!("will unpack {} into {}", files, top.display()));
+        }
+    }
+}
+
+/// Unpack `archive` straight into its final location, for
+/// `--direct`.
+///
+/// The entries are the same as in `unpack_tarball_impl`, but since
+/// nothing is unpacked to a temporary directory first, `paths_archive`
+/// is read beforehand to find out whether the entries need a new
+/// directory. It must have the same contents as `archive`.
+#[throws]
+fn unpack_direct_impl<R1: Read, R2: Read, P: FnMut(&str)>(
+    paths_archive: &mut Archive<R1>,
+    archive: &mut Archive<R2>,
+    unpack: &UnpackCommand,
+    destination: &Path,
+    warnings: &mut Warnings,
+    mut print: P,
+) {
+    if unpack.only_newer {
+        throw!(anyhow!("--direct can't be combined with --only-newer"));
+    }
+    prepare_destination(unpack, destination)?;
+
+    let UnpackPlan { entries, dir } =
+        plan_unpack(paths_archive, unpack, destination)?;
+    let target_path = match entries.first().map(|entry| &entry.path) {
+        None => {
+            if unpack.summary_json {
+                print(&UnpackSummary::default().to_json(warnings));
+            } else {
+                print("empty tarball");
+            }
+            return;
+        }
+        Some(path) if dir == destination => {
+            // OK to unwrap: entry_destination never returns an empty
+            // path
+            destination.join(path.components().next().unwrap())
+        }
+        Some(_) => dir.clone(),
+    };
+    // Unpacking into an existing target would merge into it and
+    // overwrite files, unless this is resuming an interrupted unpack
+    let resuming = unpack
+        .resume_state
+        .as_ref()
+        .is_some_and(|path| path.exists());
+    if target_path.exists() && !resuming {
+        throw!(anyhow!("{} already exists", target_path.display()));
+    }
+
+    if !dir.exists() {
+        fs::create_dir_all(&dir)?;
+        set_dir_mode(&dir, unpack.dir_mode)?;
+    }
+    let unpacked_entries = unpack_entries(archive, &dir, unpack, warnings)?;
+    if unpacked_entries.limited {
+        // OK to unwrap: only limited if a limit was set
+        print(&limit_message(unpack.limit.unwrap()));
+    } else if let Some(path) = &unpack.resume_state {
+        // Nothing left to resume
+        fs::remove_file(path)?;
+    }
+    if !unpack.summary_json {
+        print(&format!("unpacked to {}", target_path.display()));
+    }
+
+    let paths = unpacked_entries
+        .files
+        .iter()
+        .map(|file| dir.join(file))
+        .collect::<Vec<_>>();
+    let summary =
+        UnpackSummary::new(&target_path, &paths, unpacked_entries.skipped)?;
+    run_exec_all(unpack, paths.into_iter(), warnings)?;
+    if unpack.summary_json {
+        print(&summary.to_json(warnings));
+    }
+}
+
+#[throws]
+fn unpack_tarball(
+    unpack: UnpackCommand,
+    timeout: Option<Duration>,
+    warnings: &mut Warnings,
+) {
+    // TODO: decompression
+    let from_stdin = unpack.tarball == Path::new(STDIO_PATH);
+    if from_stdin && unpack.direct {
+        throw!(anyhow!("--direct can't be used when reading from stdin"));
+    }
+    if from_stdin && unpack.multi_volume {
+        throw!(anyhow!(
+            "--multi-volume can't be used when reading from stdin"
+        ));
+    }
+    let open = || -> Result<Box<dyn Read>, Error> {
+        if unpack.multi_volume {
+            Ok(with_timeout(split::open_volumes(&unpack.tarball)?, timeout))
+        } else {
+            open_tarball(&unpack.tarball, timeout)
+        }
+    };
+    let input = if from_stdin {
+        with_timeout(io::stdin(), timeout)
+    } else {
+        open()?
+    };
+    let mut archive = Archive::new(input);
+
+    let mut destination = env::current_dir()?;
+    if let Some(dir) = &unpack.directory {
+        destination = destination.join(dir);
+    }
+
+    if unpack.describe {
+        return describe_unpack_impl(
+            &mut archive,
+            &unpack,
+            &destination,
+            |s| unpack.messages.print(s),
+        )?;
+    }
+
+    // With --quiet-summary, messages are only printed if unpacking fails
+    let mut held_messages = Vec::new();
+    let print = |s: &str| {
+        if unpack.quiet_summary {
+            held_messages.push(s.to_string());
+        } else {
+            unpack.messages.print(s);
+        }
+    };
+    if unpack.resume_state.is_some() && !unpack.direct {
+        // Without --direct, an interrupted unpack leaves nothing behind
+        // to resume
+        throw!(anyhow!("--resume-state requires --direct"));
+    }
+    let result = if unpack.direct {
+        let mut paths_archive = Archive::new(open()?);
+        unpack_direct_impl(
+            &mut paths_archive,
+            &mut archive,
+            &unpack,
+            &destination,
+            warnings,
+            print,
+        )
+    } else {
+        unpack_tarball_impl(
+            &mut archive,
+            &unpack,
+            &destination,
+            warnings,
+            print,
+        )
+    };
+    if result.is_err() {
+        for message in &held_messages {
+            unpack.messages.print(message);
+        }
+    }
+    result?;
+}
+
+/// Check if `a` and `b` are paths to the same existing file.
+#[cfg(unix)]
+fn same_file(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    match (fs::metadata(a), fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+#[throws]
+fn repack_tarball(
+    repack: RepackCommand,
+    timeout: Option<Duration>,
+    warnings: &mut Warnings,
+) {
+    let from_stdin = repack.input == Path::new(STDIO_PATH);
+    let input = if from_stdin {
+        with_timeout(io::stdin(), timeout)
+    } else {
+        open_tarball(&repack.input, timeout)?
+    };
+    // When replacing the input, write to a temporary file next to it
+    // so the input is left alone if anything fails
+    let mut in_place = None;
+    let output: Box<dyn Write> = match &repack.output {
+        Some(path) if path == Path::new(STDIO_PATH) => {
+            // Stdout is line-buffered, which would mean many small
+            // writes for binary data
+            Box::new(io::BufWriter::new(io::stdout().lock()))
+        }
+        // Creating the output would truncate the input before it's read
+        Some(path) if !same_file(&repack.input, path) => {
+            Box::new(File::create(path)?)
+        }
+        _ => {
+            if from_stdin {
+                throw!(anyhow!("an output path is needed when reading stdin"));
+            }
+            let dir = match repack.input.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+            let tmp_file = tempfile::NamedTempFile::new_in(dir)?;
+            let file = tmp_file.reopen()?;
+            in_place = Some(tmp_file);
+            Box::new(file)
+        }
+    };
+    let mut archive = Archive::new(input);
+    let mut builder = Builder::new(output);
+    let transform = PathTransform {
+        strip_components: repack.strip_components,
+        exclude: repack.exclude,
+    };
+    repack::repack_tarball_impl(
+        &mut archive,
+        &mut builder,
+        &transform,
+        repack.optimize,
+        warnings,
+    )?;
+    builder.into_inner()?.flush()?;
+    if let Some(tmp_file) = in_place {
+        // Close the input before replacing it
+        drop(archive);
+        let permissions = fs::metadata(&repack.input)?.permissions();
+        fs::set_permissions(tmp_file.path(), permissions)?;
+        tmp_file.persist(&repack.input)?;
+    }
+}
+
+#[throws]
+fn pack_tarball(pack: PackCommand, warnings: &mut Warnings) {
+    let describe = if pack.git_describe {
+        Some(pack::git_describe(&pack.source)?)
+    } else {
+        None
+    };
+    if let Some(size) = pack.split {
+        let output = SplitWriter::new(&pack.output, size);
+        pack::pack_tarball_impl(
+            output,
+            &pack,
+            describe.as_deref(),
+            warnings,
+            print_str,
+        )?
+        .flush()?;
+    } else {
+        let file = File::create(&pack.output)?;
+        pack::pack_tarball_impl(
+            file,
+            &pack,
+            describe.as_deref(),
+            warnings,
+            print_str,
+        )?;
+    }
+}
+
+#[throws]
+fn main() {
+    // Read the umask before any threads start; see current_umask
+    #[cfg(unix)]
+    STARTUP_UMASK.get_or_init(swap_umask);
+
+    let opt: Opt = argh::from_env();
+
+    let timeout = opt.timeout.map(Duration::from_secs);
+
+    let mut warnings = Warnings::default();
+    let result = match opt.command {
+        Command::CatAll(cat) => cat_all(cat, timeout),
+        Command::Check(check) => check_tarball(check, timeout),
+        Command::List(list) => list_tarball(list, timeout, &mut warnings),
+        Command::Pack(pack) => pack_tarball(pack, &mut warnings),
+        Command::Repack(repack) => {
+            repack_tarball(repack, timeout, &mut warnings)
+        }
+        Command::Unpack(unpack) => {
+            unpack_tarball(unpack, timeout, &mut warnings)
+        }
+    };
+
+    for message in &warnings.messages {
+        eprintln!("warning: {}", message);
+    }
+    result?;
+
+    if opt.fail_on_warning && !warnings.messages.is_empty() {
+        throw!(anyhow!(
+            "{} warning(s) with --fail-on-warning set",
+            warnings.messages.len()
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_test_tar() -> Vec<u8> {
+        include_bytes!("../tests/test.tar").to_vec()
+    }
+
+    /// Build an in-memory tarball containing regular files.
+    fn build_tar(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, data) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_mtime(0);
+            header.set_uid(0);
+            header.set_gid(0);
+            builder.append_data(&mut header, path, *data).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    /// List the tarball `file` with `list`, returning the printed lines.
+    fn list_lines(file: &[u8], list: &ListCommand) -> Vec<String> {
+        let mut lines = Vec::new();
+        list_tarball_impl(
+            &mut Archive::new(file),
+            list,
+            &mut Warnings::default(),
+            |s| lines.push(s.to_string()),
+        )
+        .unwrap();
+        lines
+    }
+
+    #[throws]
+    fn get_dir_contents(dir: &Path) -> Vec<PathBuf> {
+        let mut contents = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            contents.push(entry.path());
+        }
+        contents.sort_unstable();
+        contents
+    }
+
+    #[test]
+    fn test_list_tarball() {
+        let file = get_test_tar();
+
+        let lines = list_lines(&file, &ListCommand::default());
+
+        assert_eq!(
+            lines,
+            vec![
+                "Cargo.lock 4.80 KiB",
+                "Cargo.toml 187 B",
+                "LICENSE    11.09 KiB",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_list_width() {
+        let file = get_test_tar();
+
+        let list = ListCommand {
+            width: Some(16),
+            ..Default::default()
+        };
+        let lines = list_lines(&file, &list);
+
+        assert_eq!(
+            lines,
+            vec!["….lock 4.80 KiB", "….toml 187 B", "…CENSE 11.09 KiB"]
+        );
+        assert!(lines.iter().all(|line| line.width() <= 16));
+
+        assert_eq!(truncate_path("a/b/c.txt", 20), "a/b/c.txt");
+        assert_eq!(truncate_path("a/b/c.txt", 6), "…c.txt");
+        assert_eq!(truncate_path("a/b/c.txt", 0), "");
+    }
+
+    #[test]
+    fn test_list_dirs_first() {
+        let file = build_tar(&[
+            ("a.txt", b"a"),
+            ("c/z.txt", b"z"),
+            ("b/x.txt", b"x"),
+            ("c/d/y.txt", b"y"),
+        ]);
+        let list = ListCommand {
+            names_only: true,
+            dirs_first: true,
+            ..Default::default()
+        };
+        let lines = list_lines(&file, &list);
+        assert_eq!(lines, vec!["b/x.txt", "c/d/y.txt", "c/z.txt", "a.txt"]);
+
+        // Directory entries sort with their contents following them
+        assert_eq!(
+            compare_dirs_first(Path::new("c"), true, Path::new("b.txt"), false),
+            Ordering::Less
+        );
+        assert_eq!(
+            compare_dirs_first(Path::new("c"), true, Path::new("c/a"), false),
+            Ordering::Less
+        );
+        assert_eq!(
+            compare_dirs_first(Path::new("a"), true, Path::new("b/c"), false),
+            Ordering::Less
+        );
+    }
+
+    #[test]
+    fn test_list_sort_compressibility() {
+        let random = (0..4096u32)
+            .map(|i| (i.wrapping_mul(2654435761) >> 24) as u8)
+            .collect::<Vec<_>>();
+        let file = build_tar(&[
+            ("a-random.bin", &random),
+            ("b-repetitive.txt", &[b'a'; 4096]),
+            ("c-text.txt", b"hello hello hello world"),
+        ]);
+        let list = ListCommand {
+            names_only: true,
+            sort: ListSort::Compressibility,
+            ..Default::default()
+        };
+        let lines = list_lines(&file, &list);
+        assert_eq!(
+            lines,
+            vec!["b-repetitive.txt", "c-text.txt", "a-random.bin"]
+        );
+    }
+
+    #[test]
+    fn test_list_show_meta() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        fs::write(tmp_dir.path().join("a.txt"), "a").unwrap();
+        let pack = PackCommand {
+            source: tmp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let file = pack::pack_tarball_impl(
+            Vec::new(),
+            &pack,
+            Some("v1.2-3-gabc"),
+            &mut Warnings::default(),
+            |_| {},
+        )
+        .unwrap();
+
+        let list = ListCommand {
+            show_meta: true,
+            ..Default::default()
+        };
+        let lines = list_lines(&file, &list);
+        assert_eq!(lines, vec!["git-describe: v1.2-3-gabc"]);
+
+        let lines = list_lines(&get_test_tar(), &list);
+        assert_eq!(lines, vec!["no metadata"]);
+    }
+
+    #[test]
+    fn test_list_classify_content() {
+        let file = build_tar(&[
+            ("a.txt", "héllo\n".as_bytes()),
+            ("b.bin", b"\x7fELF\x00\x01"),
+        ]);
+        let list = ListCommand {
+            names_only: true,
+            classify_content: true,
+            ..Default::default()
+        };
+
+        let lines = list_lines(&file, &list);
+        assert_eq!(lines, vec!["a.txt [text]", "b.bin [binary]"]);
+    }
+
+    #[test]
+    fn test_list_compressibility_by_dir() {
+        // Simple xorshift generator, so the test doesn't depend on
+        // the system's random number source
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let random = (0..4096)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect::<Vec<_>>();
+        let repetitive = b"aaaaaaaabbbb".repeat(300);
+        let file = build_tar(&[
+            ("random/a.bin", &random),
+            ("text/a.txt", &repetitive),
+            ("text/b.txt", &repetitive),
+            ("top.txt", b""),
+        ]);
+
+        let list = ListCommand {
+            compressibility_by_dir: true,
+            ..Default::default()
+        };
+        let lines = list_lines(&file, &list);
+        assert_eq!(
+            lines,
+            vec![
+                "text/: 89% compressible (2 files)",
+                "random/: 1% compressible (1 file)"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_list_precision() {
+        let file = get_test_tar();
+
+        let list = ListCommand {
+            precision: Some(0),
+            ..Default::default()
+        };
+        let lines = list_lines(&file, &list);
+        assert_eq!(
+            lines,
+            vec!["Cargo.lock 5 KiB", "Cargo.toml 187 B", "LICENSE    11 KiB"]
+        );
+
+        assert_eq!(format_size_with_precision(4911, 3).unwrap(), "4.796 KiB");
+    }
+
+    #[test]
+    fn test_list_resolve_internal_links() {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(5);
+        builder
+            .append_data(&mut header, "dir/file.txt", &b"hello"[..])
+            .unwrap();
+        for (path, target) in [
+            ("link", "dir/file.txt"),
+            ("dir/up", "../link"),
+            ("dir/self", "."),
+            ("external", "../outside"),
+            ("missing", "nowhere"),
+        ] {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(tar::EntryType::Symlink);
+            header.set_size(0);
+            header.set_link_name(target).unwrap();
+            builder.append_data(&mut header, path, io::empty()).unwrap();
+        }
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Directory);
+        header.set_size(0);
+        builder
+            .append_data(&mut header, "dir", io::empty())
+            .unwrap();
+        let file = builder.into_inner().unwrap();
+
+        let list = ListCommand {
+            names_only: true,
+            resolve_internal_links: true,
+            ..Default::default()
+        };
+        let lines = list_lines(&file, &list);
+        assert_eq!(
+            lines,
+            vec![
+                "dir",
+                "dir/file.txt",
+                "dir/self -> . (dir)",
+                "dir/up -> ../link (file, 5 B)",
+                "external -> ../outside (external)",
+                "link -> dir/file.txt (file, 5 B)",
+                "missing -> nowhere (missing)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_list_csv() {
+        let file = build_tar(&[("a,b.txt", b"ab"), ("say \"hi\"", b"hi!")]);
+        let list = ListCommand {
+            format: ListFormat::Csv,
+            ..Default::default()
+        };
+        let lines = list_lines(&file, &list);
+        assert_eq!(
+            lines,
+            vec![
+                "path,size,mode,mtime,type",
+                "\"a,b.txt\",2,0644,0,file",
+                "\"say \"\"hi\"\"\",3,0644,0,file",
+            ]
+        );
+
+        // Rows are filtered and sorted like the text output
+        let file = build_tar(&[
+            ("top/c.txt", b"c"),
+            ("top/b.md", b"b"),
+            ("top/a.txt", b"a"),
+        ]);
+        let list = ListCommand {
+            format: ListFormat::Csv,
+            match_pattern: Some("*.txt".into()),
+            relative_to: Some("top".into()),
+            ..Default::default()
+        };
+        let lines = list_lines(&file, &list);
+        assert_eq!(
+            lines,
+            vec![
+                "path,size,mode,mtime,type",
+                "a.txt,1,0644,0,file",
+                "c.txt,1,0644,0,file",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_list_mode_filters() {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, mode) in [("a", 0o755), ("b", 0o4755), ("c", 0o644)] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(0);
+            header.set_mode(mode);
+            builder.append_data(&mut header, path, io::empty()).unwrap();
+        }
+        let file = builder.into_inner().unwrap();
+
+        let mut list = ListCommand {
+            names_only: true,
+            mode_mask: Some(0o4000),
+            ..Default::default()
+        };
+        assert_eq!(list_lines(&file, &list), vec!["b"]);
+
+        list.mode_mask = None;
+        list.mode_exact = Some(0o755);
+        assert_eq!(list_lines(&file, &list), vec!["a"]);
+    }
+
+    #[test]
+    fn test_list_by_extension() {
+        let file = build_tar(&[
+            ("src/a.rs", b"abc"),
+            ("src/b.rs", b"ab"),
+            ("notes.txt", b"0123456789"),
+            ("Makefile", b"m"),
+        ]);
+
+        let list = ListCommand {
+            by_extension: true,
+            ..Default::default()
+        };
+        let lines = list_lines(&file, &list);
+
+        assert_eq!(
+            lines,
+            vec![
+                ".txt: 1 file, 10 B",
+                ".rs: 2 files, 5 B",
+                "(none): 1 file, 1 B"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_list_summary() {
+        let file = get_test_tar();
+        let mut archive = Archive::new(file.as_slice());
+
+        let mut lines = Vec::new();
+        list_summary_impl(
+            &mut archive,
+            file.len() as u64,
+            DEFAULT_SIZE_PRECISION,
+            |s| lines.push(s.to_string()),
+        )
+        .unwrap();
+
+        assert_eq!(
+            lines,
+            vec![
+                "entries:   3",
+                "stored:    20 KiB",
+                "extracted: 16.07 KiB",
+                "ratio:     0.80x",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_list_filter_logic() {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, entry_type) in [
+            ("src", tar::EntryType::Directory),
+            ("src/main.rs", tar::EntryType::Regular),
+            ("README.md", tar::EntryType::Regular),
+            ("link.rs", tar::EntryType::Symlink),
+        ] {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(entry_type);
+            header.set_size(0);
+            header.set_mode(0o644);
+            if entry_type.is_symlink() {
+                header.set_link_name("src/main.rs").unwrap();
+            }
+            builder.append_data(&mut header, path, io::empty()).unwrap();
+        }
+        let file = builder.into_inner().unwrap();
+
+        let mut list = ListCommand {
+            names_only: true,
+            entry_type: Some("file"),
+            match_pattern: Some("*.rs".into()),
+            ..Default::default()
+        };
+        assert_eq!(list_lines(&file, &list), vec!["src/main.rs"]);
+
+        list.filter_logic = FilterLogic::Or;
+        assert_eq!(
+            list_lines(&file, &list),
+            vec!["README.md", "link.rs", "src/main.rs"]
+        );
+
+        assert!(path_matches("src/*", "src/main.rs"));
+        assert!(!path_matches("*.md", "README.md/x"));
+        assert!(parse_entry_type("fifo").is_err());
+    }
+
+    #[test]
+    fn test_list_conflicts_with() {
+        let file = build_tar(&[
+            ("b.txt", b"b"),
+            ("dir/a.txt", b"a"),
+            ("dir/c.txt", b"c"),
+        ]);
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let unpack_dir = tmp_dir.path().join("myArchive");
+        fs::create_dir_all(unpack_dir.join("dir")).unwrap();
+        fs::write(unpack_dir.join("b.txt"), "old").unwrap();
+        fs::write(unpack_dir.join("dir/c.txt"), "old").unwrap();
+        fs::write(unpack_dir.join("other.txt"), "old").unwrap();
+
+        let list = ListCommand {
+            tarball: "myArchive.tar".into(),
+            names_only: true,
+            conflicts_with: Some(tmp_dir.path().to_path_buf()),
+            ..Default::default()
+        };
+        let lines = list_lines(&file, &list);
+        assert_eq!(lines, vec!["b.txt", "dir/c.txt"]);
+    }
+
+    #[test]
+    fn test_list_as_extracted() {
+        let file = build_tar(&[("b.txt", b"b"), ("dir/a.txt", b"a")]);
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+
+        let list = ListCommand {
+            tarball: "myArchive.tar".into(),
+            names_only: true,
+            as_extracted: true,
+            directory: Some(tmp_dir.path().to_path_buf()),
+            ..Default::default()
+        };
+        let lines = list_lines(&file, &list);
+
+        let unpack = UnpackCommand {
+            tarball: "myArchive.tar".into(),
+            ..Default::default()
+        };
+        unpack_tarball_impl(
+            &mut Archive::new(file.as_slice()),
+            &unpack,
+            tmp_dir.path(),
+            &mut Warnings::default(),
+            |_| {},
+        )
+        .unwrap();
+
+        let unpack_dir = tmp_dir.path().join("myArchive");
+        assert_eq!(
+            lines,
+            vec![
+                unpack_dir.join("b.txt").display().to_string(),
+                unpack_dir.join("dir/a.txt").display().to_string(),
+            ]
+        );
+        for line in lines {
+            assert!(Path::new(&line).is_file());
+        }
+
+        // Filtered out entries still affect where the rest go
+        let list = ListCommand {
+            match_pattern: Some("b.txt".into()),
+            ..list
+        };
+        let lines = list_lines(&file, &list);
+        assert_eq!(lines, vec![unpack_dir.join("b.txt").display().to_string()]);
+    }
+
+    #[test]
+    fn test_list_limit() {
+        let file = get_test_tar();
+
+        let list = ListCommand {
+            limit: Some(2),
+            ..Default::default()
+        };
+        let lines = list_lines(&file, &list);
+
+        assert_eq!(lines, vec!["Cargo.lock 4.80 KiB", "LICENSE    11.09 KiB"]);
+    }
+
+    #[test]
+    fn test_unpack_limit() {
+        let file = get_test_tar();
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+
+        let unpack = UnpackCommand {
+            tarball: "myArchive.tar".into(),
+            limit: Some(2),
+            ..Default::default()
+        };
+        let mut lines = Vec::new();
+        unpack_tarball_impl(
+            &mut Archive::new(file.as_slice()),
+            &unpack,
+            tmp_dir.path(),
+            &mut Warnings::default(),
+            |s| lines.push(s.to_string()),
+        )
+        .unwrap();
+
+        let unpack_dir = tmp_dir.path().join("myArchive");
+        assert_eq!(
+            lines,
+            vec![
+                "stopped after 2 entries due to --limit".to_string(),
+                format!("unpacked to {}", unpack_dir.display()),
+            ]
+        );
+        assert_eq!(limit_message(1), "stopped after 1 entry due to --limit");
+        assert_eq!(
+            get_dir_contents(&unpack_dir).unwrap(),
+            vec![unpack_dir.join("Cargo.lock"), unpack_dir.join("LICENSE")]
+        );
+    }
+
+    #[test]
+    fn test_list_blocks() {
+        let file = get_test_tar();
+
+        let list = ListCommand {
+            blocks: true,
+            ..Default::default()
+        };
+        let lines = list_lines(&file, &list);
+
+        assert_eq!(
+            lines,
+            vec![
+                "Cargo.lock 4.80 KiB (11 blocks)",
+                "Cargo.toml 187 B (2 blocks)",
+                "LICENSE    11.09 KiB (24 blocks)",
+            ]
+        );
+        assert_eq!(entry_blocks(0), 1);
+        assert_eq!(entry_blocks(512), 2);
+    }
+
+    #[test]
+    fn test_list_names_only() {
+        let file = get_test_tar();
+
+        let list = ListCommand {
+            names_only: true,
+            ..Default::default()
+        };
+        let lines = list_lines(&file, &list);
+
+        assert_eq!(lines, vec!["Cargo.lock", "Cargo.toml", "LICENSE"]);
+    }
+
+    #[test]
+    fn test_list_relative_to() {
+        let file = build_tar(&[
+            ("src/", b""),
+            ("src/main.rs", b"a"),
+            ("src/sub/mod.rs", b"b"),
+            ("srcs/other.rs", b"c"),
+            ("docs/README.md", b"d"),
+        ]);
+
+        let list = ListCommand {
+            relative_to: Some("src/".into()),
+            ..Default::default()
+        };
+        let lines = list_lines(&file, &list);
+
+        assert_eq!(lines, vec!["main.rs    1 B", "sub/mod.rs 1 B"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unpack_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(1);
+        header.set_mode(0o664);
+        builder
+            .append_data(&mut header, "a.txt", &b"a"[..])
+            .unwrap();
+        let file = builder.into_inner().unwrap();
+
+        let unpacked_mode = |same_permissions| {
+            let tmp_dir = tempfile::TempDir::new().unwrap();
+            let unpack = UnpackCommand {
+                tarball: "perms.tar".into(),
+                same_permissions,
+                ..Default::default()
+            };
+            unpack_tarball_impl(
+                &mut Archive::new(file.as_slice()),
+                &unpack,
+                tmp_dir.path(),
+                &mut Warnings::default(),
+                |_| {},
+            )
+            .unwrap();
+            let metadata = fs::metadata(tmp_dir.path().join("a.txt")).unwrap();
+            metadata.permissions().mode() & 0o7777
+        };
+
+        assert_eq!(unpacked_mode(false), 0o664 & !current_umask().unwrap());
+        assert_eq!(unpacked_mode(true), 0o664);
+    }
+
+    #[test]
+    fn test_unpack_only_newer() {
+        let mut builder = tar::Builder::new(Vec::new());
+        for name in ["fresh.txt", "stale.txt"] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(3);
+            header.set_mode(0o644);
+            header.set_mtime(1_000_000);
+            builder.append_data(&mut header, name, &b"new"[..]).unwrap();
+        }
+        let file = builder.into_inner().unwrap();
+
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let unpack_dir = tmp_dir.path().join("myArchive");
+        fs::create_dir(&unpack_dir).unwrap();
+        for (name, mtime) in [("fresh.txt", 2_000_000), ("stale.txt", 0)] {
+            let path = unpack_dir.join(name);
+            fs::write(&path, "old").unwrap();
+            let mtime = FileTime::from_unix_time(mtime, 0);
+            filetime::set_file_mtime(path, mtime).unwrap();
+        }
+
+        let unpack = UnpackCommand {
+            tarball: "myArchive.tar".into(),
+            only_newer: true,
+            ..Default::default()
+        };
+        let mut lines = Vec::new();
+        unpack_tarball_impl(
+            &mut Archive::new(file.as_slice()),
+            &unpack,
+            tmp_dir.path(),
+            &mut Warnings::default(),
+            |s| lines.push(s.to_string()),
+        )
+        .unwrap();
+
+        assert_eq!(
+            lines,
+            vec![format!(
+                "unpacked to {}, skipped 1 up-to-date file",
+                unpack_dir.display()
+            )]
+        );
+        assert_eq!(fs::read(unpack_dir.join("fresh.txt")).unwrap(), b"old");
+        assert_eq!(fs::read(unpack_dir.join("stale.txt")).unwrap(), b"new");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unpack_preserve_order() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Directory);
+        header.set_size(0);
+        header.set_mode(0o555);
+        builder
+            .append_data(&mut header, "dir", io::empty())
+            .unwrap();
+        for (path, data) in [
+            ("dir/a.txt", &b"first"[..]),
+            ("b.txt", b"b"),
+            ("dir/a.txt", b"last"),
+        ] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            builder.append_data(&mut header, path, data).unwrap();
+        }
+        let file = builder.into_inner().unwrap();
+        // Root can write to read-only directories
+        let is_root = unsafe { libc::geteuid() } == 0;
+
+        for preserve_order in [false, true] {
+            let tmp_dir = tempfile::TempDir::new().unwrap();
+            let unpack = UnpackCommand {
+                tarball: "myArchive.tar".into(),
+                preserve_order,
+                ..Default::default()
+            };
+            let result = unpack_tarball_impl(
+                &mut Archive::new(file.as_slice()),
+                &unpack,
+                tmp_dir.path(),
+                &mut Warnings::default(),
+                |_| {},
+            );
+
+            if preserve_order && !is_root {
+                // The directory is made read-only before its contents
+                // are unpacked
+                assert!(result.is_err());
+            } else {
+                result.unwrap();
+                let unpack_dir = tmp_dir.path().join("myArchive");
+                assert_eq!(
+                    fs::read(unpack_dir.join("dir/a.txt")).unwrap(),
+                    b"last"
+                );
+                let dir = unpack_dir.join("dir");
+                let mode = dir.metadata().unwrap().permissions().mode();
+                assert_eq!(mode & 0o777, 0o555);
+            }
+
+            // Make the directories writable again so that the
+            // temporary directory can be removed
+            let mut dirs = vec![tmp_dir.path().to_path_buf()];
+            while let Some(dir) = dirs.pop() {
+                fs::set_permissions(&dir, fs::Permissions::from_mode(0o755))
+                    .unwrap();
+                for entry in fs::read_dir(&dir).unwrap() {
+                    let entry = entry.unwrap();
+                    if entry.file_type().unwrap().is_dir() {
+                        dirs.push(entry.path());
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_unpack_mtime_now() {
+        let file = get_test_tar();
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+
+        let unpack = UnpackCommand {
+            tarball: "myArchive.tar".into(),
+            mtime_now: true,
+            ..Default::default()
+        };
+        let start = FileTime::now();
+        unpack_tarball_impl(
+            &mut Archive::new(file.as_slice()),
+            &unpack,
+            tmp_dir.path(),
+            &mut Warnings::default(),
+            |_| {},
+        )
+        .unwrap();
+
+        let unpack_dir = tmp_dir.path().join("myArchive");
+        for path in get_dir_contents(&unpack_dir).unwrap() {
+            let metadata = fs::metadata(path).unwrap();
+            let mtime = FileTime::from_last_modification_time(&metadata);
+            // Allow for filesystems with coarse timestamps
+            assert!(mtime.unix_seconds() >= start.unix_seconds() - 2);
+        }
+    }
+
+    #[test]
+    fn test_unpack_strip_components() {
+        let file = build_tar(&[
+            ("top/a.txt", b"a"),
+            ("top/README.md", b"readme"),
+            ("top/sub/b.txt", b"b"),
+        ]);
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+
+        let unpack = UnpackCommand {
+            tarball: "myArchive.tar".into(),
+            strip_components: 1,
+            exclude: vec!["*.md".into()],
+            ..Default::default()
+        };
+        unpack_tarball_impl(
+            &mut Archive::new(file.as_slice()),
+            &unpack,
+            tmp_dir.path(),
+            &mut Warnings::default(),
+            |_| {},
+        )
+        .unwrap();
+
+        let unpack_dir = tmp_dir.path().join("myArchive");
+        assert_eq!(
+            get_dir_contents(&unpack_dir).unwrap(),
+            vec![unpack_dir.join("a.txt"), unpack_dir.join("sub")]
+        );
+        assert_eq!(fs::read(unpack_dir.join("sub/b.txt")).unwrap(), b"b");
+    }
+
+    #[test]
+    fn test_unpack_direct() {
+        let file = build_tar(&[("a.txt", b"a"), ("sub/b.txt", b"b")]);
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+
+        let unpack = UnpackCommand {
+            tarball: "myArchive.tar".into(),
+            direct: true,
+            ..Default::default()
+        };
+        let mut lines = Vec::new();
+        unpack_direct_impl(
+            &mut Archive::new(file.as_slice()),
+            &mut Archive::new(file.as_slice()),
+            &unpack,
+            tmp_dir.path(),
+            &mut Warnings::default(),
+            |s| lines.push(s.to_string()),
+        )
+        .unwrap();
+
+        let unpack_dir = tmp_dir.path().join("myArchive");
+        assert_eq!(
+            lines,
+            vec![format!("unpacked to {}", unpack_dir.display())]
+        );
+        assert_eq!(
+            get_dir_contents(tmp_dir.path()).unwrap(),
+            vec![unpack_dir.clone()]
+        );
+        assert_eq!(
+            get_dir_contents(&unpack_dir).unwrap(),
+            vec![unpack_dir.join("a.txt"), unpack_dir.join("sub")]
+        );
+        assert_eq!(
+            get_dir_contents(&unpack_dir.join("sub")).unwrap(),
+            vec![unpack_dir.join("sub/b.txt")]
+        );
+    }
+
+    #[test]
+    fn test_unpack_direct_existing() {
+        let file = build_tar(&[("a.txt", b"a"), ("b.txt", b"b")]);
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let unpack_dir = tmp_dir.path().join("myArchive");
+        fs::create_dir(&unpack_dir).unwrap();
+        fs::write(unpack_dir.join("a.txt"), "old a").unwrap();
+
+        let unpack = UnpackCommand {
+            tarball: "myArchive.tar".into(),
+            direct: true,
+            ..Default::default()
+        };
+        let err = unpack_direct_impl(
+            &mut Archive::new(file.as_slice()),
+            &mut Archive::new(file.as_slice()),
+            &unpack,
+            tmp_dir.path(),
+            &mut Warnings::default(),
+            |_| {},
+        )
+        .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            format!("{} already exists", unpack_dir.display())
+        );
+        assert_eq!(fs::read(unpack_dir.join("a.txt")).unwrap(), b"old a");
+        assert!(!unpack_dir.join("b.txt").exists());
+    }
+
+    #[test]
+    fn test_unpack_resume_state() {
+        let file = build_tar(&[
+            ("a.txt", b"new a"),
+            ("b.txt", b"new b"),
+            ("c.txt", b"new c"),
+        ]);
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let state_path = tmp_dir.path().join("state");
+        let unpack_dir = tmp_dir.path().join("myArchive");
+
+        // State left behind by a run that was interrupted after the
+        // first entry, with part of the line for the second written
+        fs::create_dir(&unpack_dir).unwrap();
+        fs::write(unpack_dir.join("a.txt"), "old a").unwrap();
+        fs::write(&state_path, "0 a.txt\n1 b.").unwrap();
+
+        let unpack = UnpackCommand {
+            tarball: "myArchive.tar".into(),
+            direct: true,
+            resume_state: Some(state_path.clone()),
+            ..Default::default()
+        };
+        unpack_direct_impl(
+            &mut Archive::new(file.as_slice()),
+            &mut Archive::new(file.as_slice()),
+            &unpack,
+            tmp_dir.path(),
+            &mut Warnings::default(),
+            |_| {},
+        )
+        .unwrap();
+
+        assert_eq!(fs::read(unpack_dir.join("a.txt")).unwrap(), b"old a");
+        assert_eq!(fs::read(unpack_dir.join("b.txt")).unwrap(), b"new b");
+        assert_eq!(fs::read(unpack_dir.join("c.txt")).unwrap(), b"new c");
+        assert!(!state_path.exists());
+    }
+
+    #[test]
+    fn test_unpack_warn_permissions() {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, mode) in [("a.txt", 0o644), ("b", 0o2755)] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(1);
+            header.set_mode(mode);
+            builder.append_data(&mut header, path, &b"x"[..]).unwrap();
+        }
+        let file = builder.into_inner().unwrap();
+
+        let mut unpack = UnpackCommand {
+            tarball: "myArchive.tar".into(),
+            warn_permissions: true,
+            ..Default::default()
+        };
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let mut warnings = Warnings::default();
+        unpack_tarball_impl(
+            &mut Archive::new(file.as_slice()),
+            &unpack,
+            tmp_dir.path(),
+            &mut warnings,
+            |_| {},
+        )
+        .unwrap();
+        assert_eq!(
+            warnings.messages,
+            vec!["entry has unsafe permissions (setgid): b"]
+        );
+
+        unpack.strict = true;
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let err = unpack_tarball_impl(
+            &mut Archive::new(file.as_slice()),
+            &unpack,
+            tmp_dir.path(),
+            &mut Warnings::default(),
+            |_| {},
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "entry has unsafe permissions (setgid): b");
+    }
+
+    #[test]
+    fn test_unpack_allow_types() {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Directory);
+        header.set_size(0);
+        header.set_mode(0o755);
+        builder
+            .append_data(&mut header, "dir", io::empty())
+            .unwrap();
+        let mut header = tar::Header::new_gnu();
+        header.set_size(1);
+        header.set_mode(0o644);
+        builder
+            .append_data(&mut header, "dir/a.txt", &b"a"[..])
+            .unwrap();
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_size(0);
+        header.set_mode(0o777);
+        header.set_link_name("/etc/passwd").unwrap();
+        builder
+            .append_data(&mut header, "dir/link", io::empty())
+            .unwrap();
+        let file = builder.into_inner().unwrap();
+
+        let unpack = UnpackCommand {
+            tarball: "myArchive.tar".into(),
+            allow_types: Some(parse_entry_types("file,dir").unwrap()),
+            ..Default::default()
+        };
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let mut warnings = Warnings::default();
+        unpack_tarball_impl(
+            &mut Archive::new(file.as_slice()),
+            &unpack,
+            tmp_dir.path(),
+            &mut warnings,
+            |_| {},
+        )
+        .unwrap();
+        let dir = tmp_dir.path().join("dir");
+        assert_eq!(get_dir_contents(&dir).unwrap(), vec![dir.join("a.txt")]);
+        assert_eq!(
+            warnings.messages,
+            vec![
+                "skipped symlink entry not allowed by --allow-types: dir/link"
+            ]
+        );
+        assert!(parse_entry_types("file,fifo").is_err());
+    }
+
+    #[test]
+    fn test_unpack_long_paths() {
+        assert_eq!(add_long_path_prefix(r"C:\a\b"), r"\\?\C:\a\b");
+        assert_eq!(
+            add_long_path_prefix(r"\\server\share\a"),
+            r"\\?\UNC\server\share\a"
+        );
+        assert_eq!(add_long_path_prefix(r"\\?\C:\a"), r"\\?\C:\a");
+
+        let long_path =
+            ["d".repeat(100), "d".repeat(100), "f".repeat(100)].join("/");
+        let file = build_tar(&[("a.txt", b"a"), (&long_path, b"long")]);
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let unpack = UnpackCommand {
+            tarball: "myArchive.tar".into(),
+            skip_long_paths: true,
+            ..Default::default()
+        };
+        let mut warnings = Warnings::default();
+        unpack_tarball_impl(
+            &mut Archive::new(file.as_slice()),
+            &unpack,
+            tmp_dir.path(),
+            &mut warnings,
+            |_| {},
+        )
+        .unwrap();
+
+        assert_eq!(
+            get_dir_contents(tmp_dir.path()).unwrap(),
+            vec![tmp_dir.path().join("a.txt")]
+        );
+        assert_eq!(
+            warnings.messages,
+            vec![format!(
+                "skipped entry with a path that's too long: {}",
+                long_path
+            )]
+        );
+    }
+
+    #[test]
+    fn test_pack_split() {
+        let src_dir = tempfile::TempDir::new().unwrap();
+        let data = (0..3000).map(|i| i as u8).collect::<Vec<_>>();
+        fs::write(src_dir.path().join("data.bin"), &data).unwrap();
+        fs::write(src_dir.path().join("small.txt"), "small").unwrap();
+
+        let out_dir = tempfile::TempDir::new().unwrap();
+        let output = out_dir.path().join("out.tar");
+        let pack = PackCommand {
+            source: src_dir.path().to_path_buf(),
+            output: output.clone(),
+            split: Some(2048),
+            ..Default::default()
+        };
+        let writer = SplitWriter::new(&output, 2048);
+        pack::pack_tarball_impl(
+            writer,
+            &pack,
+            None,
+            &mut Warnings::default(),
+            |_| {},
+        )
+        .unwrap()
+        .flush()
+        .unwrap();
+        let first = split::volume_path(&output, 1);
+        assert!(split::volume_path(&output, 3).exists());
+        assert_eq!(first.metadata().unwrap().len(), 2048);
+
+        let unpack = UnpackCommand {
+            tarball: first.clone(),
+            multi_volume: true,
+            ..Default::default()
+        };
+        let dest_dir = tempfile::TempDir::new().unwrap();
+        unpack_tarball_impl(
+            &mut Archive::new(split::open_volumes(&first).unwrap()),
+            &unpack,
+            dest_dir.path(),
+            &mut Warnings::default(),
+            |_| {},
+        )
+        .unwrap();
+        let unpacked = dest_dir.path().join("out");
+        assert_eq!(fs::read(unpacked.join("data.bin")).unwrap(), data);
+        assert_eq!(fs::read(unpacked.join("small.txt")).unwrap(), b"small");
+    }
+
+    #[test]
+    fn test_unpack_summary_json() {
+        let file = get_test_tar();
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let unpack = UnpackCommand {
+            tarball: "myArchive.tar".into(),
+            exclude: vec!["LICENSE".into()],
+            summary_json: true,
+            ..Default::default()
+        };
+        let mut warnings = Warnings::default();
+        warnings.warn("a \"quoted\" warning".into());
+        let mut lines = Vec::new();
+        unpack_tarball_impl(
+            &mut Archive::new(file.as_slice()),
+            &unpack,
+            tmp_dir.path(),
+            &mut warnings,
+            |s| lines.push(s.to_string()),
+        )
+        .unwrap();
+        assert_eq!(
+            lines,
+            vec![format!(
+                concat!(
+                    "{{\"destination\":{},\"files\":2,\"bytes\":5098,",
+                    "\"skipped\":1,",
+                    "\"warnings\":[\"a \\\"quoted\\\" warning\"]}}"
+                ),
+                json_string(
+                    &tmp_dir.path().join("myArchive").to_string_lossy()
+                )
+            )]
+        );
+    }
 
-            let path = header.path()?.display().to_string();
-            let path_columns = path.width();
-            if path_columns > max_path_columns {
-                max_path_columns = path_columns;
-            }
+    #[test]
+    fn test_unpack_describe() {
+        let describe = |file: &[u8], unpack: &UnpackCommand| {
+            let mut lines = Vec::new();
+            describe_unpack_impl(
+                &mut Archive::new(file),
+                unpack,
+                Path::new("/dest"),
+                |s| lines.push(s.to_string()),
+            )
+            .unwrap();
+            lines
+        };
+        let mut unpack = UnpackCommand {
+            tarball: "myArchive.tar".into(),
+            ..Default::default()
+        };
 
-            Ok(Entry { path, size })
-        })
-        .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(
+            describe(&get_test_tar(), &unpack),
+            vec![format!(
+                "will unpack 3 files (16.07 KiB) into {}, creating it",
+                Path::new("/dest").join("myArchive").display()
+            )]
+        );
+        let file = build_tar(&[("a.txt", b"abc")]);
+        assert_eq!(
+            describe(&file, &unpack),
+            vec!["will place 1 file (3 B) in the current directory"]
+        );
+        let file = build_tar(&[("top/a.txt", b"a"), ("top/b.txt", b"b")]);
+        assert_eq!(
+            describe(&file, &unpack),
+            vec![format!(
+                "will unpack 2 files (2 B) into {}",
+                Path::new("/dest").join("top").display()
+            )]
+        );
 
-    entries.sort_unstable_by_key(|e| e.path.clone());
+        unpack.directory = Some("/dest".into());
+        let file = build_tar(&[("a.txt", b"abc")]);
+        assert_eq!(
+            describe(&file, &unpack),
+            vec!["will place 1 file (3 B) in /dest"]
+        );
+    }
 
-    for entry in entries {
-        print(&format!(
-            "{:path_width$} {}",
-            entry.path,
-            entry.size,
-            path_width = max_path_columns
-        ));
+    #[test]
+    fn test_unpack_create_dest() {
+        let file = get_test_tar();
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let destination = tmp_dir.path().join("a/b");
+        let mut unpack = UnpackCommand {
+            tarball: "myArchive.tar".into(),
+            ..Default::default()
+        };
+
+        let err = unpack_tarball_impl(
+            &mut Archive::new(file.as_slice()),
+            &unpack,
+            &destination,
+            &mut Warnings::default(),
+            |_| {},
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "destination directory does not exist: {}",
+                destination.display()
+            )
+        );
+        assert!(!tmp_dir.path().join("a").exists());
+
+        unpack.create_dest = true;
+        unpack_tarball_impl(
+            &mut Archive::new(file.as_slice()),
+            &unpack,
+            &destination,
+            &mut Warnings::default(),
+            |_| {},
+        )
+        .unwrap();
+        assert_eq!(
+            get_dir_contents(&destination).unwrap(),
+            vec![destination.join("myArchive")]
+        );
     }
-}
 
-#[throws]
-fn list_tarball(list: ListCommand) {
-    // TODO: decompression
-    let file = File::open(list.tarball).unwrap();
-    let mut archive = Archive::new(file);
+    #[cfg(unix)]
+    #[test]
+    fn test_unpack_no_follow_dest_symlinks() {
+        let file = get_test_tar();
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        // Resolve any symlinks in the temporary directory's own path
+        let tmp_path = tmp_dir.path().canonicalize().unwrap();
+        fs::create_dir(tmp_path.join("real")).unwrap();
+        std::os::unix::fs::symlink("real", tmp_path.join("link")).unwrap();
 
-    list_tarball_impl(&mut archive, print_str)?;
-}
+        let unpack = UnpackCommand {
+            tarball: "myArchive.tar".into(),
+            no_follow_dest_symlinks: true,
+            ..Default::default()
+        };
+        let err = unpack_tarball_impl(
+            &mut Archive::new(file.as_slice()),
+            &unpack,
+            &tmp_path.join("link"),
+            &mut Warnings::default(),
+            |_| {},
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "destination path contains a symlink: {}",
+                tmp_path.join("link").display()
+            )
+        );
+        assert!(get_dir_contents(&tmp_path.join("real")).unwrap().is_empty());
 
-/// This is similar to Path::file_stem, but it additionally strips off
-/// the ".tar" extension if that is present behind the first
-/// extension.
-///
-/// Examples:
-///
-///     foo.tar.gz -> foo
-///     foo.tar -> foo
-///     foo -> foo
-///     foo.bar.gz -> foo.bar
-fn file_stem(path: &Path) -> Option<&OsStr> {
-    let stem = path.file_stem()?;
-    let stem_path = Path::new(stem);
-    if stem_path.extension() == Some(OsStr::new("tar")) {
-        stem_path.file_stem()
-    } else {
-        Some(stem)
+        unpack_tarball_impl(
+            &mut Archive::new(file.as_slice()),
+            &unpack,
+            &tmp_path.join("real"),
+            &mut Warnings::default(),
+            |_| {},
+        )
+        .unwrap();
     }
-}
 
-enum DirContents {
-    /// Directory is empty
-    Empty,
-    /// Directory contains only one child (either file or directory)
-    One(PathBuf),
-    /// Directory contains multiple children (files or directories)
-    Multiple,
-}
+    #[cfg(unix)]
+    #[test]
+    fn test_unpack_dir_mode() {
+        use std::os::unix::fs::PermissionsExt;
 
-impl DirContents {
-    #[throws]
-    fn new(dir: &Path) -> DirContents {
-        // Check if there's more than one file in the temporary directory
-        let mut first_file = None;
-        let mut count = 0;
-        for entry in fs::read_dir(dir)? {
-            let entry = entry?;
-            count += 1;
-            if first_file.is_none() {
-                first_file = Some(entry.path());
+        let file = get_test_tar();
+        let mut unpack = UnpackCommand {
+            tarball: "myArchive.tar".into(),
+            dir_mode: Some(0o751),
+            ..Default::default()
+        };
+        assert_eq!(parse_octal_mode("751"), Ok(0o751));
+        assert!(parse_octal_mode("9").is_err());
+
+        for direct in [false, true] {
+            unpack.direct = direct;
+            let tmp_dir = tempfile::TempDir::new().unwrap();
+            if direct {
+                unpack_direct_impl(
+                    &mut Archive::new(file.as_slice()),
+                    &mut Archive::new(file.as_slice()),
+                    &unpack,
+                    tmp_dir.path(),
+                    &mut Warnings::default(),
+                    |_| {},
+                )
+                .unwrap();
             } else {
-                break;
+                unpack_tarball_impl(
+                    &mut Archive::new(file.as_slice()),
+                    &unpack,
+                    tmp_dir.path(),
+                    &mut Warnings::default(),
+                    |_| {},
+                )
+                .unwrap();
             }
+
+            let metadata =
+                fs::metadata(tmp_dir.path().join("myArchive")).unwrap();
+            assert_eq!(metadata.permissions().mode() & 0o7777, 0o751);
         }
 
-        if count == 0 {
-            DirContents::Empty
-        } else if count == 1 {
-            // OK to unwrap: first_file has been set if count > 0
-            DirContents::One(first_file.unwrap())
-        } else {
-            DirContents::Multiple
+        // Also applies to the destination and its parents if created
+        unpack.direct = false;
+        unpack.create_dest = true;
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let destination = tmp_dir.path().join("new/dest");
+        unpack_tarball_impl(
+            &mut Archive::new(file.as_slice()),
+            &unpack,
+            &destination,
+            &mut Warnings::default(),
+            |_| {},
+        )
+        .unwrap();
+        for dir in ["new", "new/dest", "new/dest/myArchive"] {
+            let metadata = fs::metadata(tmp_dir.path().join(dir)).unwrap();
+            assert_eq!(metadata.permissions().mode() & 0o7777, 0o751);
         }
+        let metadata = fs::metadata(tmp_dir.path()).unwrap();
+        assert_ne!(metadata.permissions().mode() & 0o7777, 0o751);
     }
-}
 
-#[throws]
-fn unpack_tarball_impl<R: Read, P: FnMut(&str)>(
-    archive: &mut Archive<R>,
-    source: &Path,
-    destination: &Path,
-    mut print: P,
-) {
-    // Unpack into a temporary directory
-    let tmp_dir = tempfile::Builder::new().tempdir_in(&destination)?;
-    archive.unpack(tmp_dir.path())?;
+    #[test]
+    fn test_unpack_always_subdir() {
+        let file = build_tar(&[("a.txt", b"a")]);
+        let mut unpack = UnpackCommand {
+            tarball: "myArchive.tar".into(),
+            always_subdir: true,
+            ..Default::default()
+        };
 
-    // Check if there's more than one file in the temporary directory
-    match DirContents::new(tmp_dir.path())? {
-        DirContents::Empty => {
-            print("empty tarball");
-        }
-        DirContents::One(path) => {
-            // OK to unwrap: this path comes from a directory listing,
-            // we know the path doesn't terminate in "..".
-            let target_path = destination.join(path.file_name().unwrap());
-            fs::rename(path, &target_path)?;
-            print(&format!("unpacked to {}", target_path.display()));
+        for direct in [false, true] {
+            unpack.direct = direct;
+            let tmp_dir = tempfile::TempDir::new().unwrap();
+            let mut lines = Vec::new();
+            if direct {
+                unpack_direct_impl(
+                    &mut Archive::new(file.as_slice()),
+                    &mut Archive::new(file.as_slice()),
+                    &unpack,
+                    tmp_dir.path(),
+                    &mut Warnings::default(),
+                    |s| lines.push(s.to_string()),
+                )
+                .unwrap();
+            } else {
+                unpack_tarball_impl(
+                    &mut Archive::new(file.as_slice()),
+                    &unpack,
+                    tmp_dir.path(),
+                    &mut Warnings::default(),
+                    |s| lines.push(s.to_string()),
+                )
+                .unwrap();
+            }
+
+            let unpack_dir = tmp_dir.path().join("myArchive");
+            assert_eq!(
+                lines,
+                vec![format!("unpacked to {}", unpack_dir.display())]
+            );
+            assert_eq!(
+                get_dir_contents(&unpack_dir).unwrap(),
+                vec![unpack_dir.join("a.txt")]
+            );
         }
-        DirContents::Multiple => {
-            // OK to unwrap: file_stem can only return None if the input
-            // path has no file component, but since we've already
-            // successfully unpacked the tarball we know the path has a
-            // file name.
-            let new_dir = destination.join(file_stem(source).unwrap());
-            // TODO: check if the target path already exists and deal with
-            // that in some way
-            let tmp_path = tmp_dir.path();
-            fs::rename(tmp_path, &new_dir)?;
-            print(&format!("unpacked to {}", new_dir.display()));
+    }
+
+    #[test]
+    fn test_parse_stdin_name() {
+        assert_eq!(parse_stdin_name("foo.tar"), Ok("foo.tar".to_string()));
+        for name in ["", ".", "..", "/", "a/b"] {
+            assert!(parse_stdin_name(name).is_err(), "{:?}", name);
         }
     }
-}
 
-#[throws]
-fn unpack_tarball(unpack: UnpackCommand) {
-    // TODO: decompression
-    let file = File::open(&unpack.tarball).unwrap();
-    let mut archive = Archive::new(file);
+    #[test]
+    fn test_unpack_stdin_name() {
+        let file = get_test_tar();
+        let mut unpack = UnpackCommand {
+            tarball: "-".into(),
+            stdin_name: Some("foo".into()),
+            ..Default::default()
+        };
 
-    let cwd = env::current_dir()?;
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        unpack_tarball_impl(
+            &mut Archive::new(file.as_slice()),
+            &unpack,
+            tmp_dir.path(),
+            &mut Warnings::default(),
+            |_| {},
+        )
+        .unwrap();
+        assert_eq!(
+            get_dir_contents(tmp_dir.path()).unwrap(),
+            vec![tmp_dir.path().join("foo")]
+        );
 
-    unpack_tarball_impl(&mut archive, &unpack.tarball, &cwd, print_str)?;
-}
+        unpack.stdin_name = None;
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        unpack_tarball_impl(
+            &mut Archive::new(file.as_slice()),
+            &unpack,
+            tmp_dir.path(),
+            &mut Warnings::default(),
+            |_| {},
+        )
+        .unwrap();
+        assert_eq!(
+            get_dir_contents(tmp_dir.path()).unwrap(),
+            vec![tmp_dir.path().join("archive")]
+        );
+    }
 
-#[throws]
-fn main() {
-    let opt: Opt = argh::from_env();
+    #[cfg(unix)]
+    #[test]
+    fn test_unpack_exec() {
+        let file = get_test_tar();
+        let tmp_dir = tempfile::TempDir::new().unwrap();
 
-    match opt.command {
-        Command::List(list) => {
-            list_tarball(list)?;
-        }
-        Command::Unpack(unpack) => {
-            unpack_tarball(unpack)?;
+        let unpack = UnpackCommand {
+            tarball: "myArchive.tar".into(),
+            exec: Some("touch {}.done".into()),
+            ..Default::default()
+        };
+        let mut warnings = Warnings::default();
+        unpack_tarball_impl(
+            &mut Archive::new(file.as_slice()),
+            &unpack,
+            tmp_dir.path(),
+            &mut warnings,
+            |_| {},
+        )
+        .unwrap();
+        assert!(warnings.messages.is_empty());
+
+        let unpack_dir = tmp_dir.path().join("myArchive");
+        for name in ["Cargo.lock", "Cargo.toml", "LICENSE"] {
+            assert!(unpack_dir.join(format!("{}.done", name)).exists());
         }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_duplicate_path_warnings() {
+        let file =
+            build_tar(&[("a.txt", b"1"), ("b.txt", b"2"), ("a.txt", b"3")]);
 
-    fn get_test_tar() -> Vec<u8> {
-        include_bytes!("../tests/test.tar").to_vec()
+        let mut warnings = Warnings::default();
+        let mut archive = Archive::new(file.as_slice());
+        list_tarball_impl(
+            &mut archive,
+            &ListCommand::default(),
+            &mut warnings,
+            |_| {},
+        )
+        .unwrap();
+        assert_eq!(warnings.messages, vec!["duplicate path in tarball: a.txt"]);
+
+        let mut warnings = Warnings::default();
+        let mut archive = Archive::new(file.as_slice());
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let unpack = UnpackCommand {
+            tarball: "dup.tar".into(),
+            ..Default::default()
+        };
+        unpack_tarball_impl(
+            &mut archive,
+            &unpack,
+            tmp_dir.path(),
+            &mut warnings,
+            |_| {},
+        )
+        .unwrap();
+        assert_eq!(warnings.messages, vec!["duplicate path in tarball: a.txt"]);
     }
 
-    #[throws]
-    fn get_dir_contents(dir: &Path) -> Vec<PathBuf> {
-        let mut contents = Vec::new();
-        for entry in fs::read_dir(dir)? {
-            let entry = entry?;
-            contents.push(entry.path());
+    #[test]
+    fn test_unpack_equivalent_paths() {
+        // The tar crate normalizes paths when writing them, so set the
+        // raw names directly
+        let mut builder = tar::Builder::new(Vec::new());
+        for (name, data) in [(&b"./a"[..], b"1"), (b"a", b"2"), (b"b//c", b"3")]
+        {
+            let mut header = tar::Header::new_gnu();
+            header.as_gnu_mut().unwrap().name[..name.len()]
+                .copy_from_slice(name);
+            header.set_size(1);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append(&header, &data[..]).unwrap();
         }
-        contents.sort_unstable();
-        contents
+        let file = builder.into_inner().unwrap();
+
+        let mut warnings = Warnings::default();
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let unpack = UnpackCommand {
+            tarball: "dup.tar".into(),
+            ..Default::default()
+        };
+        unpack_tarball_impl(
+            &mut Archive::new(file.as_slice()),
+            &unpack,
+            tmp_dir.path(),
+            &mut warnings,
+            |_| {},
+        )
+        .unwrap();
+
+        // Paths are normalized the same way as unpack_in, so "./a" and
+        // "a" are the same file and the last one wins
+        let unpack_dir = tmp_dir.path().join("dup");
+        assert_eq!(
+            get_dir_contents(&unpack_dir).unwrap(),
+            vec![unpack_dir.join("a"), unpack_dir.join("b")]
+        );
+        assert_eq!(fs::read(unpack_dir.join("a")).unwrap(), b"2");
+        assert_eq!(fs::read(unpack_dir.join("b/c")).unwrap(), b"3");
+        assert_eq!(warnings.messages, vec!["duplicate path in tarball: a"]);
     }
 
     #[test]
-    fn test_list_tarball() {
-        let file = get_test_tar();
-        let mut archive = Archive::new(file.as_slice());
+    fn test_list_duplicates() {
+        let file = build_tar(&[
+            ("a.txt", b"same"),
+            ("b.txt", b"different"),
+            ("dir/c.txt", b"same"),
+        ]);
 
-        let mut lines = Vec::new();
-        list_tarball_impl(&mut archive, |s| lines.push(s.to_string())).unwrap();
+        let list = ListCommand {
+            find_dupes: true,
+            ..Default::default()
+        };
+        let lines = list_lines(&file, &list);
 
+        let hash = hash_reader(&b"same"[..]).unwrap();
         assert_eq!(
             lines,
             vec![
-                "Cargo.lock 4.80 KiB",
-                "Cargo.toml 187 B",
-                "LICENSE    11.09 KiB",
+                format!("{:016x}", hash),
+                "  a.txt".into(),
+                "  dir/c.txt".into()
             ]
         );
     }
 
+    #[test]
+    fn test_check_reproducible() {
+        let check = CheckCommand {
+            reproducible: true,
+            ..Default::default()
+        };
+
+        let file = build_tar(&[("a.txt", b"a"), ("b/c.txt", b"c")]);
+        let mut lines = Vec::new();
+        check_tarball_impl(&mut Archive::new(file.as_slice()), &check, |s| {
+            lines.push(s.to_string())
+        })
+        .unwrap();
+        assert_eq!(lines, vec!["ok"]);
+
+        let file = build_tar(&[("b.txt", b"b"), ("a.txt", b"a")]);
+        let err = check_tarball_impl(
+            &mut Archive::new(file.as_slice()),
+            &check,
+            |_| {},
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "not reproducible: a.txt: not sorted, comes after b.txt"
+        );
+
+        let file = get_test_tar();
+        let err = check_tarball_impl(
+            &mut Archive::new(file.as_slice()),
+            &check,
+            |_| {},
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "not reproducible: LICENSE: mtime is not zero"
+        );
+    }
+
+    #[test]
+    fn test_check_size_mismatch() {
+        let check = CheckCommand {
+            size_mismatch: true,
+            ..Default::default()
+        };
+
+        let file = get_test_tar();
+        let mut lines = Vec::new();
+        check_tarball_impl(&mut Archive::new(file.as_slice()), &check, |s| {
+            lines.push(s.to_string())
+        })
+        .unwrap();
+        assert_eq!(lines, vec!["ok"]);
+
+        // Cut the data off partway through the only entry
+        let mut file = build_tar(&[("a.txt", &[b'a'; 1000])]);
+        file.truncate(512 + 100);
+        let err = check_tarball_impl(
+            &mut Archive::new(file.as_slice()),
+            &check,
+            |_| {},
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "size mismatch: a.txt: header says 1000 bytes but 100 are stored"
+        );
+    }
+
+    #[test]
+    fn test_cat_all() {
+        let file = get_test_tar();
+
+        let mut expected = Vec::new();
+        for entry in Archive::new(file.as_slice()).entries().unwrap() {
+            entry.unwrap().read_to_end(&mut expected).unwrap();
+        }
+        let mut output = Vec::new();
+        cat_all_impl(&mut Archive::new(file.as_slice()), None, &mut output)
+            .unwrap();
+        assert_eq!(output, expected);
+
+        let file = build_tar(&[("a.log", b"a\n"), ("b.log", b"b\n")]);
+        let mut output = Vec::new();
+        cat_all_impl(
+            &mut Archive::new(file.as_slice()),
+            Some("--"),
+            &mut output,
+        )
+        .unwrap();
+        assert_eq!(output, b"a\n--\nb\n");
+    }
+
     #[test]
     fn test_file_stem() {
         assert_eq!(file_stem(Path::new("foo")).unwrap(), "foo");
@@ -273,10 +4622,15 @@ mod tests {
 
         let tmp_dir = tempfile::TempDir::new().unwrap();
 
+        let unpack = UnpackCommand {
+            tarball: "myArchive.tar".into(),
+            ..Default::default()
+        };
         unpack_tarball_impl(
             &mut archive,
-            Path::new("myArchive.tar"),
+            &unpack,
             tmp_dir.path(),
+            &mut Warnings::default(),
             |s| lines.push(s.to_string()),
         )
         .unwrap();
@@ -297,4 +4651,36 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn test_unpack_verify() {
+        let file = get_test_tar();
+        let mut archive = Archive::new(file.as_slice());
+
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let unpack = UnpackCommand {
+            tarball: "myArchive.tar".into(),
+            verify: true,
+            ..Default::default()
+        };
+        unpack_tarball_impl(
+            &mut archive,
+            &unpack,
+            tmp_dir.path(),
+            &mut Warnings::default(),
+            |_| {},
+        )
+        .unwrap();
+
+        let unpack_dir = tmp_dir.path().join("myArchive");
+        let expected = include_bytes!("../tests/test.tar");
+        let mut archive = Archive::new(&expected[..]);
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let path = unpack_dir.join(entry.path().unwrap());
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data).unwrap();
+            assert_eq!(fs::read(path).unwrap(), data);
+        }
+    }
 }
@@ -0,0 +1,808 @@
+use crate::glob::glob_match;
+use crate::link::append_link;
+use crate::{format_size, hash_reader, PackCommand, Warnings};
+use anyhow::{anyhow, Error};
+use fehler::{throw, throws};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Component, Path, PathBuf, MAIN_SEPARATOR};
+use std::process;
+use tar::{Builder, EntryType, Header};
+
+/// Name of the file in the source root that lists paths to exclude.
+const IGNORE_FILE_NAME: &str = ".tarignore";
+
+/// Name of the entry that records information about how the tarball
+/// was made, as `key: value` lines.
+pub const META_FILE_NAME: &str = ".tarr-meta";
+
+/// One line of an ignore file.
+struct IgnorePattern {
+    glob: String,
+    /// Pattern started with `!`, so matching paths are re-included.
+    negated: bool,
+    /// Pattern ended with `/`, so it only matches directories.
+    dir_only: bool,
+    /// Pattern contains a `/`, so it matches the path relative to the
+    /// root rather than just the file name.
+    anchored: bool,
+}
+
+/// Exclusion rules read from a `.tarignore` file.
+///
+/// This supports the commonly-used subset of gitignore syntax:
+/// comments, `!` negation, a trailing `/` to match only directories,
+/// and the `*`, `?`, and `**` wildcards. As with git, the last
+/// matching pattern wins, and patterns containing a slash are
+/// relative to the root. Only the ignore file in the root of the
+/// source directory is read.
+#[derive(Default)]
+pub struct IgnoreFile {
+    patterns: Vec<IgnorePattern>,
+}
+
+impl IgnoreFile {
+    /// Read the ignore file at `path`. A missing file excludes nothing.
+    #[throws]
+    pub fn load(path: &Path) -> IgnoreFile {
+        match fs::read_to_string(path) {
+            Ok(contents) => IgnoreFile::parse(&contents),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                IgnoreFile::default()
+            }
+            Err(err) => throw!(err),
+        }
+    }
+
+    pub fn parse(contents: &str) -> IgnoreFile {
+        let mut patterns = Vec::new();
+        for line in contents.lines() {
+            let mut line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let negated = line.starts_with('!');
+            // A leading backslash escapes a literal `#` or `!`
+            let escaped = line.starts_with("\\#") || line.starts_with("\\!");
+            if negated || escaped {
+                line = &line[1..];
+            }
+
+            let dir_only = line.ends_with('/');
+            let line = line.trim_end_matches('/');
+            let anchored = line.contains('/');
+            let line = line.trim_start_matches('/');
+            if line.is_empty() {
+                continue;
+            }
+
+            patterns.push(IgnorePattern {
+                glob: line.to_string(),
+                negated,
+                dir_only,
+                anchored,
+            });
+        }
+        IgnoreFile { patterns }
+    }
+
+    /// Check if `path` (relative to the root, `/`-separated) should be
+    /// excluded.
+    pub fn is_ignored(&self, path: &str, is_dir: bool) -> bool {
+        let name = path.rsplit('/').next().unwrap_or(path);
+        let mut ignored = false;
+        for pattern in &self.patterns {
+            if pattern.dir_only && !is_dir {
+                continue;
+            }
+            let text = if pattern.anchored { path } else { name };
+            if glob_match(&pattern.glob, text) {
+                ignored = !pattern.negated;
+            }
+        }
+        ignored
+    }
+}
+
+/// Writer that counts the bytes written through it.
+struct CountingWriter<W: Write> {
+    inner: W,
+    count: u64,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        self.count += len as u64;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Get the `git describe` output for the repository containing `dir`.
+#[throws]
+pub fn git_describe(dir: &Path) -> String {
+    let output = process::Command::new("git")
+        .args(["describe", "--always", "--dirty"])
+        .current_dir(dir)
+        .output()?;
+    if !output.status.success() {
+        throw!(anyhow!(
+            "git describe failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    String::from_utf8_lossy(&output.stdout).trim().to_string()
+}
+
+/// Write a tarball of `pack.source` to `output`, returning `output`
+/// once the tarball is complete.
+///
+/// If `git_describe` is set, it's recorded in a `.tarr-meta` entry at
+/// the start of the tarball, inside the `--single-root` directory if
+/// there is one.
+#[throws]
+pub fn pack_tarball_impl<W: Write, P: FnMut(&str)>(
+    output: W,
+    pack: &PackCommand,
+    git_describe: Option<&str>,
+    warnings: &mut Warnings,
+    print: P,
+) -> W {
+    let ignore = if pack.no_ignore_file {
+        IgnoreFile::default()
+    } else {
+        IgnoreFile::load(&pack.source.join(IGNORE_FILE_NAME))?
+    };
+
+    let mut packer = Packer {
+        builder: Builder::new(CountingWriter {
+            inner: output,
+            count: 0,
+        }),
+        pack,
+        ignore,
+        warnings,
+        print,
+        links: HashMap::new(),
+        contents: HashMap::new(),
+        root: PathBuf::new(),
+        output: output_path(&pack.output),
+    };
+    // Store symlinks as links rather than copying their targets
+    packer.builder.follow_symlinks(false);
+
+    let root = match &pack.single_root {
+        Some(root) => {
+            let root = Path::new(root);
+            if root
+                .components()
+                .any(|c| !matches!(c, Component::Normal(_)))
+            {
+                throw!(anyhow!("invalid --single-root: {}", root.display()));
+            }
+            packer.builder.append_dir(
+                to_archive_path(root, MAIN_SEPARATOR),
+                &pack.source,
+            )?;
+            root
+        }
+        None => Path::new(""),
+    };
+    packer.root = root.to_path_buf();
+
+    if let Some(describe) = git_describe {
+        let meta = format!("git-describe: {}\n", describe);
+        let mut header = Header::new_gnu();
+        header.set_size(meta.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(0);
+        header.set_uid(0);
+        header.set_gid(0);
+        packer.builder.append_data(
+            &mut header,
+            to_archive_path(&root.join(META_FILE_NAME), MAIN_SEPARATOR),
+            meta.as_bytes(),
+        )?;
+    }
+
+    packer.append_dir_contents(&pack.source, root)?;
+    packer.builder.into_inner()?.inner
+}
+
+/// Convert `path` to the form stored in the archive.
+///
+/// Archive paths always use `/` as the separator so that archives
+/// created on Windows unpack correctly elsewhere. `separator` is the
+/// path separator of the OS that `path` came from.
+fn to_archive_path(path: &Path, separator: char) -> Cow<'_, Path> {
+    if separator == '/' {
+        return Cow::Borrowed(path);
+    }
+    match path.to_str() {
+        Some(s) if s.contains(separator) => {
+            Cow::Owned(PathBuf::from(s.replace(separator, "/")))
+        }
+        _ => Cow::Borrowed(path),
+    }
+}
+
+/// Get the device and inode of a file that has more than one hard
+/// link, which identify the file across all of its links.
+#[cfg(unix)]
+fn hard_link_key(metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+
+    if metadata.is_file() && metadata.nlink() > 1 {
+        Some((metadata.dev(), metadata.ino()))
+    } else {
+        None
+    }
+}
+
+#[cfg(not(unix))]
+fn hard_link_key(_metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// Check if `metadata` is that of a Unix domain socket.
+#[cfg(unix)]
+fn is_socket(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+
+    metadata.file_type().is_socket()
+}
+
+#[cfg(not(unix))]
+fn is_socket(_metadata: &fs::Metadata) -> bool {
+    false
+}
+
+/// Get the canonical path of the tarball `output`, which may not exist
+/// yet. Returns `None` if its directory doesn't exist.
+fn output_path(output: &Path) -> Option<PathBuf> {
+    let dir = match output.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    Some(fs::canonicalize(dir).ok()?.join(output.file_name()?))
+}
+
+/// Check if the files at `a` and `b` have the same contents.
+fn same_contents(a: &Path, b: &Path) -> io::Result<bool> {
+    let mut a = BufReader::new(File::open(a)?);
+    let mut b = BufReader::new(File::open(b)?);
+    loop {
+        let buf_a = a.fill_buf()?;
+        let buf_b = b.fill_buf()?;
+        if buf_a.is_empty() || buf_b.is_empty() {
+            return Ok(buf_a.is_empty() && buf_b.is_empty());
+        }
+        let len = buf_a.len().min(buf_b.len());
+        if buf_a[..len] != buf_b[..len] {
+            return Ok(false);
+        }
+        a.consume(len);
+        b.consume(len);
+    }
+}
+
+/// State for adding the contents of a directory to a tarball.
+struct Packer<'a, W: Write, P: FnMut(&str)> {
+    builder: Builder<CountingWriter<W>>,
+    pack: &'a PackCommand,
+    ignore: IgnoreFile,
+    warnings: &'a mut Warnings,
+    print: P,
+    /// Archive paths of files added so far that have other hard
+    /// links, keyed by `hard_link_key`.
+    links: HashMap<(u64, u64), PathBuf>,
+    /// Source and archive paths of files added so far, keyed by size
+    /// and content hash, for `--dedup-content`.
+    contents: HashMap<(u64, u64), Vec<(PathBuf, PathBuf)>>,
+    /// Directory that all entries are placed under, from
+    /// `--single-root`. This is empty if not set.
+    root: PathBuf,
+    /// Canonical path of the tarball being written, so that it isn't
+    /// added to itself if it's inside the source directory.
+    output: Option<PathBuf>,
+}
+
+impl<'a, W: Write, P: FnMut(&str)> Packer<'a, W, P> {
+    /// Get the archive path of an earlier entry that the file at
+    /// `path` can be stored as a hard link to, either because it's the
+    /// same file (`--detect-hardlinks`) or has the same contents
+    /// (`--dedup-content`). Otherwise, remember the file as
+    /// `archive_name` for later files to link to.
+    #[throws]
+    fn link_target(
+        &mut self,
+        path: &Path,
+        metadata: &fs::Metadata,
+        archive_name: &Path,
+    ) -> Option<PathBuf> {
+        if self.pack.detect_hardlinks {
+            if let Some(key) = hard_link_key(metadata) {
+                if let Some(target) = self.links.get(&key) {
+                    return Some(target.clone());
+                }
+                self.links.insert(key, archive_name.to_path_buf());
+            }
+        }
+
+        // Empty files are no bigger than a link to one
+        if self.pack.dedup_content && metadata.is_file() && metadata.len() > 0 {
+            let key = (metadata.len(), hash_reader(File::open(path)?)?);
+            let candidates = self.contents.entry(key).or_default();
+            for (source, target) in candidates.iter() {
+                // The hash is only 64 bits, so check for a collision
+                if same_contents(source, path)? {
+                    return Some(target.clone());
+                }
+            }
+            candidates.push((path.to_path_buf(), archive_name.to_path_buf()));
+        }
+        None
+    }
+
+    /// Check if `path`, which is canonical, is the tarball being
+    /// written, or one of its volumes with `--split`.
+    fn is_output(&self, path: &Path) -> bool {
+        let output = match &self.output {
+            Some(output) => output,
+            None => return false,
+        };
+        if path == output {
+            return true;
+        }
+        if self.pack.split.is_none() || path.parent() != output.parent() {
+            return false;
+        }
+        // Volumes are named like "out.tar.001"
+        let (name, output_name) = match (path.file_name(), output.file_name()) {
+            (Some(name), Some(output_name)) => (name, output_name),
+            _ => return false,
+        };
+        let name = name.to_string_lossy();
+        name.strip_prefix(&*output_name.to_string_lossy())
+            .and_then(|rest| rest.strip_prefix('.'))
+            .is_some_and(|number| {
+                !number.is_empty() && number.chars().all(|c| c.is_ascii_digit())
+            })
+    }
+
+    /// Recursively add the children of `dir` to the archive.
+    /// `archive_dir` is the path of `dir` within the archive.
+    #[throws]
+    fn append_dir_contents(&mut self, dir: &Path, archive_dir: &Path) {
+        let mut children = fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+        // Sort so that the output doesn't depend on directory order
+        children.sort_unstable_by_key(|child| child.file_name());
+        let canonical_dir = fs::canonicalize(dir)?;
+
+        for child in children {
+            if self.is_output(&canonical_dir.join(child.file_name())) {
+                continue;
+            }
+            let path = child.path();
+            let archive_path = archive_dir.join(child.file_name());
+            let archive_name = to_archive_path(&archive_path, MAIN_SEPARATOR);
+            let metadata = child.metadata()?;
+            let is_dir = metadata.is_dir();
+
+            // Ignore patterns are relative to the source directory, so
+            // match without the root
+            let source_name = archive_path.strip_prefix(&self.root)?;
+            let source_name = to_archive_path(source_name, MAIN_SEPARATOR);
+            if self
+                .ignore
+                .is_ignored(&source_name.to_string_lossy(), is_dir)
+            {
+                continue;
+            }
+            // Sockets can't be stored in a tarball
+            if is_socket(&metadata) {
+                self.warnings
+                    .warn(format!("{}: socket ignored", path.display()));
+                continue;
+            }
+
+            if let Some(target) =
+                self.link_target(&path, &metadata, &archive_name)?
+            {
+                let mut header = Header::new_gnu();
+                header.set_metadata(&metadata);
+                header.set_entry_type(EntryType::Link);
+                header.set_size(0);
+                append_link(
+                    &mut self.builder,
+                    &mut header,
+                    &archive_name,
+                    &target,
+                    io::empty(),
+                )?;
+                if self.pack.verbose > 0 {
+                    (self.print)(&format!(
+                        "{} (link to {})",
+                        archive_name.display(),
+                        target.display()
+                    ));
+                }
+                continue;
+            }
+
+            self.builder.append_path_with_name(&path, &archive_name)?;
+            if is_dir {
+                self.append_dir_contents(&path, &archive_path)?;
+            } else if self.pack.verbose > 0 {
+                let mut line = format!(
+                    "{} {}",
+                    archive_name.display(),
+                    format_size(metadata.len())?
+                );
+                if self.pack.verbose > 1 {
+                    let total = format_size(self.builder.get_ref().count)?;
+                    line += &format!(" (archive size: {})", total);
+                }
+                (self.print)(&line);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tar::Archive;
+
+    #[throws]
+    fn pack_to_paths(pack: &PackCommand) -> Vec<String> {
+        let data = pack_tarball_impl(
+            Vec::new(),
+            pack,
+            None,
+            &mut Warnings::default(),
+            |_| {},
+        )?;
+
+        let mut archive = Archive::new(data.as_slice());
+        let mut paths = Vec::new();
+        for entry in archive.entries()? {
+            paths.push(entry?.path()?.display().to_string());
+        }
+        paths
+    }
+
+    #[test]
+    fn test_pack_ignore_file() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let src = tmp_dir.path();
+        fs::write(src.join(".tarignore"), "# logs\n*.log\n").unwrap();
+        fs::write(src.join("a.txt"), "a").unwrap();
+        fs::write(src.join("b.log"), "b").unwrap();
+        fs::create_dir(src.join("sub")).unwrap();
+        fs::write(src.join("sub/c.log"), "c").unwrap();
+        fs::write(src.join("sub/d.txt"), "d").unwrap();
+
+        let mut pack = PackCommand {
+            source: src.to_path_buf(),
+            ..Default::default()
+        };
+        assert_eq!(
+            pack_to_paths(&pack).unwrap(),
+            vec![".tarignore", "a.txt", "sub", "sub/d.txt"]
+        );
+
+        pack.no_ignore_file = true;
+        assert_eq!(
+            pack_to_paths(&pack).unwrap(),
+            vec![
+                ".tarignore",
+                "a.txt",
+                "b.log",
+                "sub",
+                "sub/c.log",
+                "sub/d.txt"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pack_skips_output() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let src = tmp_dir.path();
+        fs::write(src.join("a.txt"), "a").unwrap();
+        fs::write(src.join("out.tar"), "").unwrap();
+        fs::write(src.join("out.tar.001"), "").unwrap();
+
+        let mut pack = PackCommand {
+            source: src.to_path_buf(),
+            output: src.join("out.tar"),
+            ..Default::default()
+        };
+        assert_eq!(pack_to_paths(&pack).unwrap(), vec!["a.txt", "out.tar.001"]);
+
+        // With --split, the volumes are the output
+        pack.output = src.join("sub/../out.tar");
+        fs::create_dir(src.join("sub")).unwrap();
+        pack.split = Some(1024);
+        assert_eq!(pack_to_paths(&pack).unwrap(), vec!["a.txt", "sub"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_pack_skips_sockets() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let src = tmp_dir.path();
+        fs::write(src.join("a.txt"), "a").unwrap();
+        let sock = src.join("sock");
+        let _listener = std::os::unix::net::UnixListener::bind(&sock).unwrap();
+
+        let pack = PackCommand {
+            source: src.to_path_buf(),
+            ..Default::default()
+        };
+        let mut warnings = Warnings::default();
+        let data =
+            pack_tarball_impl(Vec::new(), &pack, None, &mut warnings, |_| {})
+                .unwrap();
+
+        let mut archive = Archive::new(data.as_slice());
+        let paths = archive
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().path().unwrap().display().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(paths, vec!["a.txt"]);
+        assert_eq!(
+            warnings.messages,
+            vec![format!("{}: socket ignored", sock.display())]
+        );
+    }
+
+    #[test]
+    fn test_pack_single_root() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let src = tmp_dir.path();
+        fs::write(src.join(".tarignore"), "/b.txt\n").unwrap();
+        fs::write(src.join("a.txt"), "a").unwrap();
+        fs::write(src.join("b.txt"), "b").unwrap();
+        fs::create_dir(src.join("sub")).unwrap();
+        fs::write(src.join("sub/c.txt"), "c").unwrap();
+
+        let mut pack = PackCommand {
+            source: src.to_path_buf(),
+            single_root: Some("app".into()),
+            ..Default::default()
+        };
+        assert_eq!(
+            pack_to_paths(&pack).unwrap(),
+            vec![
+                "app",
+                "app/.tarignore",
+                "app/a.txt",
+                "app/sub",
+                "app/sub/c.txt"
+            ]
+        );
+
+        pack.single_root = Some("../app".into());
+        assert!(pack_to_paths(&pack).is_err());
+    }
+
+    #[test]
+    fn test_pack_verbose() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let src = tmp_dir.path();
+        fs::write(src.join("a.txt"), "a").unwrap();
+        fs::create_dir(src.join("sub")).unwrap();
+        fs::write(src.join("sub/b.txt"), "bb").unwrap();
+
+        let mut pack = PackCommand {
+            source: src.to_path_buf(),
+            verbose: 1,
+            ..Default::default()
+        };
+        let mut lines = Vec::new();
+        pack_tarball_impl(
+            Vec::new(),
+            &pack,
+            None,
+            &mut Warnings::default(),
+            |s| lines.push(s.to_string()),
+        )
+        .unwrap();
+        assert_eq!(lines, vec!["a.txt 1 B", "sub/b.txt 2 B"]);
+
+        pack.verbose = 2;
+        let mut lines = Vec::new();
+        pack_tarball_impl(
+            Vec::new(),
+            &pack,
+            None,
+            &mut Warnings::default(),
+            |s| lines.push(s.to_string()),
+        )
+        .unwrap();
+        assert_eq!(
+            lines,
+            vec![
+                "a.txt 1 B (archive size: 1 KiB)",
+                "sub/b.txt 2 B (archive size: 2.50 KiB)"
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_pack_detect_hardlinks() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let src = tmp_dir.path();
+        fs::write(src.join("a.txt"), "shared").unwrap();
+        fs::hard_link(src.join("a.txt"), src.join("b.txt")).unwrap();
+
+        let pack = PackCommand {
+            source: src.to_path_buf(),
+            detect_hardlinks: true,
+            ..Default::default()
+        };
+        let data = pack_tarball_impl(
+            Vec::new(),
+            &pack,
+            None,
+            &mut Warnings::default(),
+            |_| {},
+        )
+        .unwrap();
+
+        let mut archive = Archive::new(data.as_slice());
+        let entries = archive
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                (
+                    entry.path().unwrap().display().to_string(),
+                    entry.header().entry_type(),
+                    entry.link_name().unwrap().map(|p| p.into_owned()),
+                    entry.size(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            entries,
+            vec![
+                ("a.txt".to_string(), EntryType::Regular, None, 6),
+                (
+                    "b.txt".to_string(),
+                    EntryType::Link,
+                    Some(PathBuf::from("a.txt")),
+                    0
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pack_dedup_content() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let src = tmp_dir.path();
+        fs::write(src.join("a.txt"), "same").unwrap();
+        fs::write(src.join("b.txt"), "same").unwrap();
+        fs::write(src.join("c.txt"), "diff").unwrap();
+
+        let pack = PackCommand {
+            source: src.to_path_buf(),
+            dedup_content: true,
+            ..Default::default()
+        };
+        let data = pack_tarball_impl(
+            Vec::new(),
+            &pack,
+            None,
+            &mut Warnings::default(),
+            |_| {},
+        )
+        .unwrap();
+
+        let mut archive = Archive::new(data.as_slice());
+        let entries = archive
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                (
+                    entry.path().unwrap().display().to_string(),
+                    entry.header().entry_type(),
+                    entry.link_name().unwrap().map(|p| p.into_owned()),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            entries,
+            vec![
+                ("a.txt".to_string(), EntryType::Regular, None),
+                (
+                    "b.txt".to_string(),
+                    EntryType::Link,
+                    Some(PathBuf::from("a.txt"))
+                ),
+                ("c.txt".to_string(), EntryType::Regular, None),
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_pack_long_link_targets() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let src = tmp_dir.path();
+        let long_dir = "d".repeat(120);
+        fs::create_dir(src.join(&long_dir)).unwrap();
+        fs::write(src.join(&long_dir).join("a.txt"), "same").unwrap();
+        fs::write(src.join("z.txt"), "same").unwrap();
+        let long_target = format!("{}/a.txt", long_dir);
+        std::os::unix::fs::symlink(&long_target, src.join("link")).unwrap();
+
+        let pack = PackCommand {
+            source: src.to_path_buf(),
+            dedup_content: true,
+            ..Default::default()
+        };
+        let data = pack_tarball_impl(
+            Vec::new(),
+            &pack,
+            None,
+            &mut Warnings::default(),
+            |_| {},
+        )
+        .unwrap();
+
+        let mut archive = Archive::new(data.as_slice());
+        let links = archive
+            .entries()
+            .unwrap()
+            .filter_map(|entry| {
+                let entry = entry.unwrap();
+                let target = entry.link_name().unwrap()?.into_owned();
+                Some((entry.path().unwrap().display().to_string(), target))
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            links,
+            vec![
+                ("link".to_string(), PathBuf::from(&long_target)),
+                ("z.txt".to_string(), PathBuf::from(&long_target)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_to_archive_path() {
+        assert_eq!(
+            to_archive_path(Path::new("src\\bin\\main.rs"), '\\'),
+            Path::new("src/bin/main.rs")
+        );
+        assert_eq!(
+            to_archive_path(Path::new("src/main.rs"), '/'),
+            Path::new("src/main.rs")
+        );
+    }
+
+    #[test]
+    fn test_ignore_file_rules() {
+        let ignore = IgnoreFile::parse("build/\n/top.txt\n*.tmp\n!keep.tmp\n");
+        assert!(ignore.is_ignored("build", true));
+        assert!(!ignore.is_ignored("build", false));
+        assert!(ignore.is_ignored("top.txt", false));
+        assert!(!ignore.is_ignored("sub/top.txt", false));
+        assert!(ignore.is_ignored("sub/x.tmp", false));
+        assert!(!ignore.is_ignored("sub/keep.tmp", false));
+    }
+}
@@ -0,0 +1,369 @@
+use crate::link::append_link;
+use crate::transform::PathTransform;
+use crate::Warnings;
+use anyhow::Error;
+use fehler::throws;
+use std::collections::{BTreeMap, HashSet};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use tar::{Archive, Builder, Header};
+
+/// An entry held in memory by `repack --optimize`.
+struct HeldEntry {
+    path: PathBuf,
+    header: Header,
+    /// Target of a symlink or hard link. A hard link's target must be
+    /// written before it.
+    link: Option<PathBuf>,
+    data: Vec<u8>,
+}
+
+impl HeldEntry {
+    /// Get the path this entry must come after, if any.
+    fn hard_link_target(&self) -> Option<&PathBuf> {
+        self.link
+            .as_ref()
+            .filter(|_| self.header.entry_type().is_hard_link())
+    }
+}
+
+/// Append an entry to `builder`, with the link target `link` if it's a
+/// symlink or hard link.
+///
+/// The target is set separately since the header only has room for
+/// the first 100 bytes of it.
+#[throws]
+fn append_entry<W: Write, R: Read>(
+    builder: &mut Builder<W>,
+    header: &mut Header,
+    path: &Path,
+    link: Option<&Path>,
+    data: R,
+) {
+    match link {
+        Some(target) => append_link(builder, header, path, target, data)?,
+        None => builder.append_data(header, path, data)?,
+    }
+}
+
+/// Copy the entries of `archive` to `builder`, changing their paths
+/// with `transform` and leaving out any that it skips.
+///
+/// If `optimize` is set, the output is made canonical: only the last
+/// entry with each path is kept, entries are sorted by path, and
+/// timestamps and owners are cleared, as `check --reproducible`
+/// expects. Hard links are kept after their targets, even if that
+/// puts them out of order. This holds the whole tarball in memory.
+#[throws]
+pub fn repack_tarball_impl<R: Read, W: Write>(
+    archive: &mut Archive<R>,
+    builder: &mut Builder<W>,
+    transform: &PathTransform,
+    optimize: bool,
+    warnings: &mut Warnings,
+) {
+    // With `optimize`, maps each path, as a string so that it sorts
+    // the same way as the check, to the last entry with that path
+    let mut optimized: BTreeMap<String, HeldEntry> = BTreeMap::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        let new_path = match transform.apply(&path) {
+            Some(new_path) => new_path,
+            None => continue,
+        };
+
+        let mut header = entry.header().clone();
+        // Read from the entry rather than the header, which only has
+        // the first 100 bytes of long targets
+        let mut link = entry.link_name()?.map(|target| target.into_owned());
+        if header.entry_type().is_hard_link() {
+            // Hard link targets are paths within the tarball, so they
+            // need to change along with the entry paths
+            if let Some(target) = link {
+                match transform.apply(&target) {
+                    Some(new_target) => link = Some(new_target),
+                    None => {
+                        warnings.warn(format!(
+                            "skipped hard link {} because its target {} \
+                             was removed",
+                            path.display(),
+                            target.display()
+                        ));
+                        continue;
+                    }
+                }
+            }
+        }
+
+        if optimize {
+            header.set_mtime(0);
+            header.set_uid(0);
+            header.set_gid(0);
+            // Old V7 headers don't have owner names
+            if header.as_ustar().is_some() || header.as_gnu().is_some() {
+                header.set_username("")?;
+                header.set_groupname("")?;
+            }
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data)?;
+            let key = new_path.to_string_lossy().into_owned();
+            let held = HeldEntry {
+                path: new_path,
+                header,
+                link,
+                data,
+            };
+            optimized.insert(key, held);
+        } else {
+            append_entry(
+                builder,
+                &mut header,
+                &new_path,
+                link.as_deref(),
+                &mut entry,
+            )?;
+        }
+    }
+
+    // Maps the targets of hard links that come before them in path
+    // order to those links, which are written once the target is
+    let mut waiting: BTreeMap<PathBuf, Vec<HeldEntry>> = BTreeMap::new();
+    let mut written = HashSet::new();
+    for held in optimized.into_values() {
+        if let Some(target) = held.hard_link_target() {
+            if !written.contains(target) {
+                waiting.entry(target.clone()).or_default().push(held);
+                continue;
+            }
+        }
+        let mut ready = vec![held];
+        while let Some(mut held) = ready.pop() {
+            append_entry(
+                builder,
+                &mut held.header,
+                &held.path,
+                held.link.as_deref(),
+                held.data.as_slice(),
+            )?;
+            ready.extend(waiting.remove(&held.path).unwrap_or_default());
+            written.insert(held.path);
+        }
+    }
+    // Links to targets that aren't in the tarball
+    for mut held in waiting.into_values().flatten() {
+        append_entry(
+            builder,
+            &mut held.header,
+            &held.path,
+            held.link.as_deref(),
+            held.data.as_slice(),
+        )?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repack() {
+        let mut builder = Builder::new(Vec::new());
+        for (path, data) in [
+            ("top/a.txt", &b"a"[..]),
+            ("top/README.md", b"readme"),
+            ("top/sub/b.txt", b"b"),
+        ] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            builder.append_data(&mut header, path, data).unwrap();
+        }
+        let input = builder.into_inner().unwrap();
+
+        let transform = PathTransform {
+            strip_components: 1,
+            exclude: vec!["*.md".into()],
+        };
+        let mut builder = Builder::new(Vec::new());
+        repack_tarball_impl(
+            &mut Archive::new(input.as_slice()),
+            &mut builder,
+            &transform,
+            false,
+            &mut Warnings::default(),
+        )
+        .unwrap();
+        let output = builder.into_inner().unwrap();
+
+        let mut archive = Archive::new(output.as_slice());
+        let mut entries = Vec::new();
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let path = entry.path().unwrap().display().to_string();
+            let mut data = String::new();
+            entry.read_to_string(&mut data).unwrap();
+            entries.push((path, data));
+        }
+        assert_eq!(
+            entries,
+            vec![
+                ("a.txt".to_string(), "a".to_string()),
+                ("sub/b.txt".to_string(), "b".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_repack_optimize() {
+        let mut builder = Builder::new(Vec::new());
+        for (path, data) in [
+            ("b.txt", &b"old"[..]),
+            ("a/z.txt", b"z"),
+            ("a.txt", b"a"),
+            ("b.txt", b"new"),
+            ("c.txt", b"v7"),
+        ] {
+            let mut header = if path == "c.txt" {
+                tar::Header::new_old()
+            } else {
+                let mut header = tar::Header::new_gnu();
+                header.set_username("someone").unwrap();
+                header
+            };
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_mtime(1_600_000_000);
+            header.set_uid(1000);
+            builder.append_data(&mut header, path, data).unwrap();
+        }
+        let input = builder.into_inner().unwrap();
+
+        let mut builder = Builder::new(Vec::new());
+        repack_tarball_impl(
+            &mut Archive::new(input.as_slice()),
+            &mut builder,
+            &PathTransform::default(),
+            true,
+            &mut Warnings::default(),
+        )
+        .unwrap();
+        let output = builder.into_inner().unwrap();
+
+        let mut archive = Archive::new(output.as_slice());
+        let mut entries = Vec::new();
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let header = entry.header();
+            assert_eq!(header.mtime().unwrap(), 0);
+            assert_eq!(header.uid().unwrap(), 0);
+            let path = entry.path().unwrap().display().to_string();
+            let username = header.username().unwrap();
+            if path == "c.txt" {
+                assert_eq!(username, None);
+            } else {
+                assert_eq!(username, Some(""));
+            }
+            let mut data = String::new();
+            entry.read_to_string(&mut data).unwrap();
+            entries.push((path, data));
+        }
+        assert_eq!(
+            entries,
+            vec![
+                ("a.txt".to_string(), "a".to_string()),
+                ("a/z.txt".to_string(), "z".to_string()),
+                ("b.txt".to_string(), "new".to_string()),
+                ("c.txt".to_string(), "v7".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_repack_optimize_links() {
+        let mut builder = Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(1);
+        header.set_mode(0o644);
+        builder.append_data(&mut header, "a/x", &b"x"[..]).unwrap();
+        for (path, target) in [("a.txt", "a/x"), ("0.txt", "a.txt")] {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(tar::EntryType::Link);
+            header.set_size(0);
+            header.set_mode(0o644);
+            header.set_link_name(target).unwrap();
+            builder
+                .append_data(&mut header, path, std::io::empty())
+                .unwrap();
+        }
+        let input = builder.into_inner().unwrap();
+
+        let mut builder = Builder::new(Vec::new());
+        repack_tarball_impl(
+            &mut Archive::new(input.as_slice()),
+            &mut builder,
+            &PathTransform::default(),
+            true,
+            &mut Warnings::default(),
+        )
+        .unwrap();
+        let output = builder.into_inner().unwrap();
+
+        let mut archive = Archive::new(output.as_slice());
+        let paths = archive
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().path().unwrap().display().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(paths, vec!["a/x", "a.txt", "0.txt"]);
+
+        // The links can be unpacked since their targets come first
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        Archive::new(output.as_slice())
+            .unpack(tmp_dir.path())
+            .unwrap();
+        let data = std::fs::read(tmp_dir.path().join("0.txt")).unwrap();
+        assert_eq!(data, b"x");
+    }
+
+    #[test]
+    fn test_repack_long_symlink() {
+        let target = "x".repeat(150);
+        let mut builder = Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_size(0);
+        header.set_mode(0o777);
+        append_link(
+            &mut builder,
+            &mut header,
+            Path::new("top/link"),
+            Path::new(&target),
+            std::io::empty(),
+        )
+        .unwrap();
+        let input = builder.into_inner().unwrap();
+
+        for optimize in [false, true] {
+            let mut builder = Builder::new(Vec::new());
+            let transform = PathTransform {
+                strip_components: 1,
+                ..Default::default()
+            };
+            repack_tarball_impl(
+                &mut Archive::new(input.as_slice()),
+                &mut builder,
+                &transform,
+                optimize,
+                &mut Warnings::default(),
+            )
+            .unwrap();
+            let output = builder.into_inner().unwrap();
+
+            let mut archive = Archive::new(output.as_slice());
+            let entry = archive.entries().unwrap().next().unwrap().unwrap();
+            assert_eq!(entry.path().unwrap(), Path::new("link"));
+            assert_eq!(entry.link_name().unwrap().unwrap(), Path::new(&target));
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use anyhow::{anyhow, Error};
+use fehler::{throw, throws};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Parse a size such as "512", "64KiB", or "100MiB".
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let digits = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(digits);
+    let multiplier = match unit {
+        "" | "B" => 1,
+        "KiB" => 1 << 10,
+        "MiB" => 1 << 20,
+        "GiB" => 1 << 30,
+        _ => return Err(format!("invalid size: {}", s)),
+    };
+    match number
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+    {
+        Some(size) if size > 0 => Ok(size),
+        _ => Err(format!("invalid size: {}", s)),
+    }
+}
+
+/// Get the path of a volume, numbered from 1: "out.tar" becomes
+/// "out.tar.001".
+pub fn volume_path(base: &Path, number: usize) -> PathBuf {
+    let mut path = base.as_os_str().to_owned();
+    path.push(format!(".{:03}", number));
+    PathBuf::from(path)
+}
+
+/// Writer that splits its output across volumes of at most
+/// `volume_size` bytes, starting a new file each time one fills up.
+pub struct SplitWriter {
+    base: PathBuf,
+    volume_size: u64,
+    current: Option<File>,
+    written: u64,
+    volumes: usize,
+}
+
+impl SplitWriter {
+    pub fn new(base: &Path, volume_size: u64) -> SplitWriter {
+        SplitWriter {
+            base: base.to_path_buf(),
+            volume_size,
+            current: None,
+            written: 0,
+            volumes: 0,
+        }
+    }
+}
+
+impl Write for SplitWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.current.is_none() || self.written == self.volume_size {
+            self.volumes += 1;
+            let path = volume_path(&self.base, self.volumes);
+            self.current = Some(File::create(path)?);
+            self.written = 0;
+        }
+        let remaining = (self.volume_size - self.written) as usize;
+        let len = buf.len().min(remaining);
+        // OK to unwrap: set above if missing
+        let len = self.current.as_mut().unwrap().write(&buf[..len])?;
+        self.written += len as u64;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.current {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Open the volumes of a split tarball, given the path of the first
+/// one, as a single reader. Volumes are read in order until the next
+/// number is missing.
+#[throws]
+pub fn open_volumes(first: &Path) -> Box<dyn Read + Send> {
+    let name = first.to_string_lossy();
+    let base = match name.strip_suffix(".001") {
+        Some(base) => PathBuf::from(base),
+        None => throw!(anyhow!(
+            "not the first volume of a split tarball: {}",
+            first.display()
+        )),
+    };
+    let mut reader: Box<dyn Read + Send> = Box::new(File::open(first)?);
+    for number in 2.. {
+        let path = volume_path(&base, number);
+        if !path.exists() {
+            break;
+        }
+        reader = Box::new(reader.chain(File::open(path)?));
+    }
+    reader
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split() {
+        let tmp_dir = tempfile::TempDir::new().unwrap();
+        let base = tmp_dir.path().join("out.tar");
+        let data = (0..2500).map(|i| i as u8).collect::<Vec<_>>();
+
+        let mut writer = SplitWriter::new(&base, 1024);
+        writer.write_all(&data).unwrap();
+        writer.flush().unwrap();
+
+        let sizes = (1..=4)
+            .map(|n| volume_path(&base, n).metadata().map(|m| m.len()).ok())
+            .collect::<Vec<_>>();
+        assert_eq!(sizes, vec![Some(1024), Some(1024), Some(452), None]);
+
+        let mut joined = Vec::new();
+        open_volumes(&volume_path(&base, 1))
+            .unwrap()
+            .read_to_end(&mut joined)
+            .unwrap();
+        assert_eq!(joined, data);
+        assert!(open_volumes(&base).is_err());
+
+        assert_eq!(parse_size("100MiB"), Ok(100 << 20));
+        assert_eq!(parse_size("512"), Ok(512));
+        assert!(parse_size("0").is_err());
+        assert!(parse_size("5MB").is_err());
+    }
+}
@@ -0,0 +1,136 @@
+use std::io::{self, Read};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+/// Size of each read done by the reader thread.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Reader that fails with a `TimedOut` error if the inner reader
+/// doesn't return anything within a time limit.
+///
+/// The inner reader is read on a separate thread so that a stalled
+/// read can't block the caller. If it never returns, that thread is
+/// left behind, which is fine since the error ends the command.
+pub struct TimeoutReader {
+    receiver: Receiver<io::Result<Vec<u8>>>,
+    timeout: Duration,
+    chunk: Vec<u8>,
+    pos: usize,
+    done: bool,
+}
+
+impl TimeoutReader {
+    pub fn new<R: Read + Send + 'static>(
+        mut inner: R,
+        timeout: Duration,
+    ) -> TimeoutReader {
+        // Only read one chunk ahead
+        let (sender, receiver) = mpsc::sync_channel(1);
+        thread::spawn(move || loop {
+            let mut chunk = vec![0; CHUNK_SIZE];
+            let result = inner.read(&mut chunk).map(|len| {
+                chunk.truncate(len);
+                chunk
+            });
+            let stop = !matches!(&result, Ok(chunk) if !chunk.is_empty());
+            if sender.send(result).is_err() || stop {
+                break;
+            }
+        });
+
+        TimeoutReader {
+            receiver,
+            timeout,
+            chunk: Vec::new(),
+            pos: 0,
+            done: false,
+        }
+    }
+}
+
+impl Read for TimeoutReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.chunk.len() {
+            if self.done {
+                return Ok(0);
+            }
+            match self.receiver.recv_timeout(self.timeout) {
+                Ok(result) => {
+                    self.chunk = result?;
+                    self.pos = 0;
+                    if self.chunk.is_empty() {
+                        self.done = true;
+                        return Ok(0);
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        format!(
+                            "timed out after {} seconds without reading any \
+                             data",
+                            self.timeout.as_secs_f64()
+                        ),
+                    ));
+                }
+                // The thread only exits after sending the end of the
+                // data or an error
+                Err(RecvTimeoutError::Disconnected) => {
+                    self.done = true;
+                    return Ok(0);
+                }
+            }
+        }
+
+        let len = buf.len().min(self.chunk.len() - self.pos);
+        buf[..len].copy_from_slice(&self.chunk[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reader that returns some data, then blocks until `stall` is
+    /// dropped.
+    struct StallingReader {
+        data: Option<Vec<u8>>,
+        stall: Receiver<()>,
+    }
+
+    impl Read for StallingReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if let Some(data) = self.data.take() {
+                buf[..data.len()].copy_from_slice(&data);
+                return Ok(data.len());
+            }
+            let _ = self.stall.recv();
+            Ok(0)
+        }
+    }
+
+    #[test]
+    fn test_timeout_reader() {
+        let (_stall, receiver) = mpsc::channel();
+        let inner = StallingReader {
+            data: Some(b"abc".to_vec()),
+            stall: receiver,
+        };
+        let mut reader = TimeoutReader::new(inner, Duration::from_millis(50));
+
+        let mut buf = [0; 8];
+        assert_eq!(reader.read(&mut buf).unwrap(), 3);
+        assert_eq!(&buf[..3], b"abc");
+        let err = reader.read(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+
+        let mut reader =
+            TimeoutReader::new(&b"done"[..], Duration::from_millis(50));
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"done");
+    }
+}
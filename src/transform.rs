@@ -0,0 +1,88 @@
+use crate::glob::glob_match;
+use std::path::{Component, Path, PathBuf};
+
+/// Changes to entry paths requested with `--strip-components` and
+/// `--exclude`, shared by the commands that read entries and write
+/// them somewhere else.
+#[derive(Debug, Default)]
+pub struct PathTransform {
+    /// Number of leading components to remove from each path.
+    pub strip_components: usize,
+    /// Patterns of paths to skip. Patterns without a `/` match any
+    /// single component of a path, so `*.md` matches `docs/README.md`
+    /// and `target` matches everything under `target/`. Patterns with
+    /// a `/` match the whole path or one of its parent directories.
+    pub exclude: Vec<String>,
+}
+
+impl PathTransform {
+    /// Check if this leaves all paths unchanged.
+    pub fn is_identity(&self) -> bool {
+        self.strip_components == 0 && self.exclude.is_empty()
+    }
+
+    /// Get the new path for an entry with path `path`, or `None` if
+    /// the entry should be skipped, either because it's excluded or
+    /// because nothing is left after stripping components.
+    ///
+    /// Root and `.` components are dropped and don't count towards
+    /// `strip_components`.
+    pub fn apply(&self, path: &Path) -> Option<PathBuf> {
+        let components = path
+            .components()
+            .filter(|c| !matches!(c, Component::RootDir | Component::CurDir))
+            .collect::<Vec<_>>();
+
+        if self.is_excluded(&components) {
+            return None;
+        }
+
+        let stripped = components
+            .into_iter()
+            .skip(self.strip_components)
+            .collect::<PathBuf>();
+        if stripped.as_os_str().is_empty() {
+            None
+        } else {
+            Some(stripped)
+        }
+    }
+
+    fn is_excluded(&self, components: &[Component]) -> bool {
+        let names = components
+            .iter()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>();
+
+        self.exclude.iter().any(|pattern| {
+            if pattern.contains('/') {
+                let pattern = pattern.trim_end_matches('/');
+                (1..=names.len())
+                    .any(|len| glob_match(pattern, &names[..len].join("/")))
+            } else {
+                names.iter().any(|name| glob_match(pattern, name))
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_path_transform() {
+        let transform = PathTransform {
+            strip_components: 1,
+            exclude: vec!["*.md".into(), "top/build/".into()],
+        };
+        let apply = |path| transform.apply(Path::new(path));
+
+        assert_eq!(apply("top/a.txt"), Some("a.txt".into()));
+        assert_eq!(apply("./top/sub/b.txt"), Some("sub/b.txt".into()));
+        assert_eq!(apply("top/"), None);
+        assert_eq!(apply("top/docs/README.md"), None);
+        assert_eq!(apply("top/build/out.o"), None);
+        assert_eq!(apply("other/build/out.o"), Some("build/out.o".into()));
+    }
+}
@@ -0,0 +1,147 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+fn test_tar() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/test.tar")
+}
+
+/// Run tarr with `args` in `dir`.
+fn tarr(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_tarr"))
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .unwrap()
+}
+
+/// Run tarr with `args` in `dir` and check that it succeeded.
+fn run_tarr(dir: &Path, args: &[&str]) -> Output {
+    let output = tarr(dir, args);
+    assert!(output.status.success(), "{:?}", output);
+    output
+}
+
+#[test]
+fn test_unpack_messages() {
+    let tarball = test_tar();
+    let tarball = tarball.to_str().unwrap();
+
+    // Messages go to stderr by default
+    let tmp_dir = tempfile::TempDir::new().unwrap();
+    let output = run_tarr(tmp_dir.path(), &["unpack", tarball]);
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .starts_with("unpacked to "));
+
+    let tmp_dir = tempfile::TempDir::new().unwrap();
+    let output =
+        run_tarr(tmp_dir.path(), &["unpack", tarball, "--messages", "stdout"]);
+    assert!(output.stderr.is_empty());
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .starts_with("unpacked to "));
+}
+
+#[test]
+fn test_fail_on_warning() {
+    let tmp_dir = tempfile::TempDir::new().unwrap();
+    let tarball = tmp_dir.path().join("dup.tar");
+    let mut builder =
+        tar::Builder::new(std::fs::File::create(&tarball).unwrap());
+    for data in [b"1", b"2"] {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(1);
+        header.set_mode(0o644);
+        builder
+            .append_data(&mut header, "a.txt", &data[..])
+            .unwrap();
+    }
+    builder.finish().unwrap();
+    drop(builder);
+    let tarball = tarball.to_str().unwrap();
+
+    let output = run_tarr(tmp_dir.path(), &["list", tarball]);
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("warning: duplicate path in tarball: a.txt"));
+
+    let output = tarr(tmp_dir.path(), &["--fail-on-warning", "list", tarball]);
+    assert!(!output.status.success());
+    // The listing is still printed
+    assert!(String::from_utf8(output.stdout).unwrap().contains("a.txt"));
+}
+
+#[test]
+fn test_repack_stdio() {
+    let tmp_dir = tempfile::TempDir::new().unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_tarr"))
+        .args(["repack", "--exclude", "*.lock", "--", "-", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let input = std::fs::read(test_tar()).unwrap();
+    child.stdin.take().unwrap().write_all(&input).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+
+    let tarball = tmp_dir.path().join("out.tar");
+    std::fs::write(&tarball, output.stdout).unwrap();
+    let output = run_tarr(
+        tmp_dir.path(),
+        &["list", "--names-only", tarball.to_str().unwrap()],
+    );
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "Cargo.toml\nLICENSE\n"
+    );
+}
+
+#[test]
+fn test_repack_same_output() {
+    let tmp_dir = tempfile::TempDir::new().unwrap();
+    std::fs::copy(test_tar(), tmp_dir.path().join("in.tar")).unwrap();
+
+    run_tarr(
+        tmp_dir.path(),
+        &["repack", "--exclude", "*.lock", "in.tar", "./in.tar"],
+    );
+    let output = run_tarr(tmp_dir.path(), &["list", "--names-only", "in.tar"]);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "Cargo.toml\nLICENSE\n"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_unpack_quiet_summary() {
+    let tarball = test_tar();
+    let tarball = tarball.to_str().unwrap();
+
+    let tmp_dir = tempfile::TempDir::new().unwrap();
+    let output =
+        run_tarr(tmp_dir.path(), &["unpack", tarball, "--quiet-summary"]);
+    assert!(output.stdout.is_empty());
+    assert!(output.stderr.is_empty());
+
+    // Make unpacking fail after the tarball has been unpacked
+    let tmp_dir = tempfile::TempDir::new().unwrap();
+    let output = tarr(
+        tmp_dir.path(),
+        &[
+            "unpack",
+            tarball,
+            "--quiet-summary",
+            "--exec",
+            "false",
+            "--exec-fail-stop",
+        ],
+    );
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("unpacked to "), "{}", stderr);
+    assert!(stderr.contains("false exited with"), "{}", stderr);
+}